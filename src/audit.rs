@@ -0,0 +1,185 @@
+//! record rollout results on the workload in the cluster
+use crate::{Error, Kind, Result, Rollout, State, Verdict};
+
+use k8s_openapi::api::{
+    apps::v1::{ControllerRevision, DaemonSet, Deployment, ReplicaSet, StatefulSet},
+    batch::v1::Job,
+    core::v1::ObjectReference,
+};
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::events::{Event, EventType, Recorder, Reporter},
+    Resource, ResourceExt,
+};
+use serde_json::json;
+use std::time::Duration;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Annotation with the hash of the last revision irae tracked
+pub const LAST_REVISION_ANNOTATION: &str = "irae/last-revision";
+/// Annotation with how tracking of the last revision concluded
+pub const LAST_OUTCOME_ANNOTATION: &str = "irae/last-outcome";
+/// Annotation with when tracking of the last revision concluded
+pub const LAST_TRACKED_ANNOTATION: &str = "irae/last-tracked";
+/// Annotation on a revision (replicaset or controllerrevision) with how its rollout concluded
+pub const OUTCOME_ANNOTATION: &str = "irae/outcome";
+/// Annotation on a revision with the seconds its rollout took
+pub const DURATION_ANNOTATION: &str = "irae/duration";
+
+impl Rollout {
+    /// Publish a Kubernetes Event on the workload for how tracking concluded
+    pub async fn publish_verdict(&self, verdict: &Verdict, duration: Duration) -> Result<()> {
+        let (type_, reason) = match verdict {
+            Verdict::Succeeded | Verdict::Unchanged => (EventType::Normal, "RolloutSucceeded"),
+            Verdict::TimedOut => (EventType::Warning, "RolloutTimedOut"),
+            Verdict::WrongVersion(_) => (EventType::Warning, "RolloutWrongVersion"),
+            Verdict::Failed(_) => (EventType::Warning, "RolloutFailed"),
+            Verdict::Superseded(_) => (EventType::Warning, "RolloutSuperseded"),
+            Verdict::Paused => (EventType::Warning, "RolloutPaused"),
+        };
+        let note = format!("Rollout {verdict} after {}s", duration.as_secs());
+        self.publish(type_, reason, note).await
+    }
+
+    /// Publish a Kubernetes Event on the workload for a rollout that could not be tracked
+    pub async fn publish_error(&self, err: &Error, duration: Duration) -> Result<()> {
+        let note = format!("Rollout failed after {}s: {err}", duration.as_secs());
+        self.publish(EventType::Warning, "RolloutFailed", note).await
+    }
+
+    async fn publish(&self, type_: EventType, reason: &str, mut note: String) -> Result<()> {
+        note.truncate(1024); // events api limit
+        let reporter = Reporter {
+            controller: "irae".into(),
+            instance: None,
+        };
+        let recorder = Recorder::new(self.client.clone(), reporter);
+        let event = Event {
+            type_,
+            reason: reason.into(),
+            note: Some(note),
+            action: "TrackRollout".into(),
+            secondary: None,
+        };
+        let reference = self.object_reference().await?;
+        recorder.publish(&event, &reference).await.map_err(Error::Kube)
+    }
+
+    /// Annotate the workload with the last tracked revision, its verdict and a timestamp
+    ///
+    /// The tracked revision itself is also annotated with the verdict and duration (for `history`).
+    /// Only metadata is patched, so this does not trigger a new rollout.
+    pub async fn annotate_verdict(&self, state: &State, verdict: &Verdict, duration: Duration) -> Result<()> {
+        let now = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .map_err(|e| Error::Time(e.into()))?;
+        let patch = last_tracked(state, verdict, now);
+        let pp = PatchParams::default();
+        let patch = Patch::Merge(&patch);
+        let res = match self.workload {
+            Kind::Deployment => self
+                .ns::<Deployment>()
+                .patch(&self.name, &pp, &patch)
+                .await
+                .map(|_| ()),
+            Kind::StatefulSet => self
+                .ns::<StatefulSet>()
+                .patch(&self.name, &pp, &patch)
+                .await
+                .map(|_| ()),
+            Kind::DaemonSet => self
+                .ns::<DaemonSet>()
+                .patch(&self.name, &pp, &patch)
+                .await
+                .map(|_| ()),
+            Kind::Job => self.ns::<Job>().patch(&self.name, &pp, &patch).await.map(|_| ()),
+        };
+        res.map_err(Error::Kube)?;
+        self.annotate_revision(state, verdict, duration).await
+    }
+
+    async fn annotate_revision(&self, state: &State, verdict: &Verdict, duration: Duration) -> Result<()> {
+        let Some(hash) = &state.hash else {
+            return Ok(()); // nothing pinned (jobs and daemonsets are tracked through their status only)
+        };
+        let patch = json!({
+            "metadata": {
+                "annotations": {
+                    OUTCOME_ANNOTATION: verdict.to_string(),
+                    DURATION_ANNOTATION: duration.as_secs().to_string(),
+                }
+            }
+        });
+        let pp = PatchParams::default();
+        let patch = Patch::Merge(&patch);
+        let res = match self.workload {
+            Kind::Deployment => {
+                let Some(rs) = self.get_rs(&state.selector).await? else {
+                    return Ok(());
+                };
+                self.ns::<ReplicaSet>()
+                    .patch(&rs.name_any(), &pp, &patch)
+                    .await
+                    .map(|_| ())
+            }
+            // statefulset hashes are the names of their controllerrevisions
+            Kind::StatefulSet | Kind::DaemonSet => self
+                .ns::<ControllerRevision>()
+                .patch(hash, &pp, &patch)
+                .await
+                .map(|_| ()),
+            Kind::Job => Ok(()), // jobs have no revisions
+        };
+        res.map_err(Error::Kube)
+    }
+
+    async fn object_reference(&self) -> Result<ObjectReference> {
+        let reference = match self.workload {
+            Kind::Deployment => self.get_deploy().await?.object_ref(&()),
+            Kind::StatefulSet => self.get_statefulset().await?.object_ref(&()),
+            Kind::DaemonSet => self.get_daemonset().await?.object_ref(&()),
+            Kind::Job => self.get_job().await?.object_ref(&()),
+        };
+        Ok(reference)
+    }
+}
+
+/// Merge patch with the annotations of the last tracked rollout of a workload
+fn last_tracked(state: &State, verdict: &Verdict, now: String) -> serde_json::Value {
+    let mut annotations = json!({
+        LAST_OUTCOME_ANNOTATION: verdict.to_string(),
+        LAST_TRACKED_ANNOTATION: now,
+    });
+    // a null would delete the revision annotated by an earlier rollout
+    if let Some(hash) = &state.hash {
+        annotations[LAST_REVISION_ANNOTATION] = hash.clone().into();
+    }
+    json!({ "metadata": { "annotations": annotations } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn keeps_the_last_revision_without_a_pinned_one() {
+        let now = "2026-10-18T12:00:00Z".to_string();
+        let mut state = testing::state(2);
+        let unpinned = last_tracked(&state, &Verdict::Unchanged, now.clone());
+        assert_eq!(
+            unpinned,
+            json!({ "metadata": { "annotations": {
+                "irae/last-outcome": "no change",
+                "irae/last-tracked": "2026-10-18T12:00:00Z",
+            }}})
+        );
+        state.pin_hash(&Kind::Deployment, "7c9d8f".into());
+        let pinned = last_tracked(&state, &Verdict::Succeeded, now);
+        assert_eq!(
+            pinned["metadata"]["annotations"][LAST_REVISION_ANNOTATION],
+            "7c9d8f"
+        );
+    }
+}
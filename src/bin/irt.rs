@@ -1,5 +1,12 @@
-use irae::{Kind, Result, Rollout};
-use std::str::FromStr;
+use clap::CommandFactory;
+use clap_complete::{engine::ArgValueCompleter, CompletionCandidate};
+use irae::{
+    config::{Config, Group, Target, WorkloadConfig},
+    replay::{Recorder, Replay},
+    term::{ci::Ci, Progress, Render, Stage, Tracked},
+    Kind, Result, RollbackPolicy, Rollout, SupersedePolicy, Verdict, VersionScheme,
+};
+use std::{collections::BTreeMap, ffi::OsStr, path::PathBuf, str::FromStr};
 
 #[derive(Debug, Clone, PartialEq)]
 enum Workload {
@@ -9,9 +16,36 @@ enum Workload {
     StatefulSet(String, Option<String>),
     // A daemonset with a namespace (if different from context)
     DaemonSet(String, Option<String>),
+    /// A job with a namespace (if different from context)
+    Job(String, Option<String>),
     // TODO: ks,
 }
 
+impl Workload {
+    /// A rollout of this workload, defaulting to the given namespace
+    fn rollout(self, namespace: &Option<String>, client: &kube::Client) -> Rollout {
+        let (kind, name, ns) = match self {
+            Workload::Deployment(name, ns) => (Kind::Deployment, name, ns),
+            Workload::StatefulSet(name, ns) => (Kind::StatefulSet, name, ns),
+            Workload::DaemonSet(name, ns) => (Kind::DaemonSet, name, ns),
+            Workload::Job(name, ns) => (Kind::Job, name, ns),
+        };
+        Rollout {
+            name,
+            namespace: ns.or_else(|| namespace.clone()),
+            workload: kind,
+            on_supersede: SupersedePolicy::default(),
+            client: client.clone(),
+            timeout: None,
+            container: None,
+            expect_version: None,
+            versioning: Default::default(),
+            poll: Default::default(),
+            cluster: None,
+        }
+    }
+}
+
 impl FromStr for Workload {
     type Err = anyhow::Error;
 
@@ -27,7 +61,8 @@ impl FromStr for Workload {
             "deploy" | "deployment" => Ok(Self::Deployment(name, ns)),
             "sts" | "statefulset" => Ok(Self::StatefulSet(name, ns)),
             "ds" | "daemonset" => Ok(Self::DaemonSet(name, ns)),
-            _ => anyhow::bail!("unknown kind: {kind}. we support deploy/sts/ds"),
+            "job" => Ok(Self::Job(name, ns)),
+            _ => anyhow::bail!("unknown kind: {kind}. we support deploy/sts/ds/job"),
         }
     }
 }
@@ -35,31 +70,258 @@ impl FromStr for Workload {
 #[derive(clap::Parser, Debug)]
 #[clap(arg_required_else_help = true)]
 struct Irt {
+    #[command(flatten)]
+    kube: KubeArgs,
+
     #[command(subcommand)]
     command: Command,
 }
 
+/// Cluster connection options
+///
+/// These follow kubectl conventions so irt also works as a `kubectl irt` plugin
+/// (when installed or symlinked as `kubectl-irt` on the PATH).
+#[derive(clap::Args, Debug)]
+struct KubeArgs {
+    /// The name of the kubeconfig context to use
+    ///
+    /// Can be repeated for track to track every workload in each context.
+    #[clap(long, global = true)]
+    context: Vec<String>,
+
+    /// Path to the kubeconfig file to use
+    #[clap(long, global = true)]
+    kubeconfig: Option<PathBuf>,
+}
+
+impl KubeArgs {
+    /// Client for the single context chosen
+    async fn client(&self) -> anyhow::Result<kube::Client> {
+        if self.context.len() > 1 {
+            anyhow::bail!("multiple contexts are only supported by track");
+        }
+        self.client_for(self.context.first().map(String::as_str)).await
+    }
+
+    /// Client for a context (or the current context)
+    async fn client_for(&self, context: Option<&str>) -> anyhow::Result<kube::Client> {
+        use kube::config::{Config, KubeConfigOptions, Kubeconfig};
+        let options = KubeConfigOptions {
+            context: context.map(String::from),
+            ..Default::default()
+        };
+        let config = match (&self.kubeconfig, context) {
+            (Some(path), _) => Config::from_custom_kubeconfig(Kubeconfig::read_from(path)?, &options).await?,
+            (None, Some(_)) => Config::from_kubeconfig(&options).await?,
+            (None, None) => Config::infer().await?, // allows in-cluster config
+        };
+        Ok(kube::Client::try_from(config)?)
+    }
+}
+
 #[derive(clap::Subcommand, Debug)]
 /// Commands for terminal irae
 pub enum Command {
     /// Track workload(s)
     Track(TrackArgs),
-    // TODO: doctor / diagnose / ..
+    /// Show the rollout status of workload(s) without waiting
+    Status(StatusArgs),
+    /// Diagnose the current rollout of a workload without waiting
+    Diagnose(DiagnoseArgs),
+    /// Show the revision history of a workload
+    History(HistoryArgs),
+    /// Watch and track every rollout in the cluster
+    Serve(ServeArgs),
+    /// Re-run tracking offline against a session recorded by track --record
+    Replay(ReplayArgs),
+    /// Print a shell completion script
+    ///
+    /// Example: source <(irt completions bash)
+    Completions {
+        /// The shell to complete for
+        shell: clap_complete::Shell,
+    },
 }
 
 #[derive(clap::Parser, Debug)]
 pub struct TrackArgs {
     /// Comma-separated list of workloads to track
     ///
-    /// Example: --workloads="monitoring/deploy/grafana,monitoring/sts/prometheus"
-    #[clap(long, short = 'w', use_value_delimiter = true, value_parser = Workload::from_str)]
-    workloads: Vec<Workload>,
+    /// Workloads without a context are tracked in every --context given.
+    ///
+    /// Example: --workloads="monitoring/deploy/grafana,prod-eu:monitoring/sts/prometheus"
+    #[clap(long, short = 'w', use_value_delimiter = true, value_parser = Target::from_str, add = ArgValueCompleter::new(complete_workloads))]
+    workloads: Vec<Target>,
+
+    /// Version (image tag or version label) or image digest (sha256:..) every ready pod must run
+    ///
+    /// Applies to --workloads; config files set versions per workload.
+    #[clap(long)]
+    expect_version: Option<String>,
+
+    /// Label with the version of a revision (default app.kubernetes.io/version)
+    ///
+    /// Used to find the newest replicaset of deployments and to check --expect-version.
+    /// Applies to --workloads; config files set labels per workload.
+    #[clap(long)]
+    version_label: Option<String>,
+
+    /// How versions in the version label are ordered: semver, calver, or created (e.g. git shas)
+    #[clap(long, value_parser = VersionScheme::from_str)]
+    version_scheme: Option<VersionScheme>,
+
+    /// Track the workloads in a config file (after any --workloads)
+    ///
+    /// The file sets per-workload timeouts, expected versions, main containers,
+    /// rollback policies, and notification targets. Its groups are tracked as stages
+    /// in order, and a failing stage skips the rest.
+    #[clap(long, short = 'f')]
+    config: Option<PathBuf>,
 
     /// The namespace to use for all workloads
     ///
     /// This overrides for all workloads not already set.
-    #[clap(short = 'n', long)]
+    #[clap(short = 'n', long, add = ArgValueCompleter::new(complete_namespaces))]
+    namespace: Option<String>,
+
+    /// Number of log lines to show from each failing container
+    #[clap(long, default_value_t = 30)]
+    log_lines: i64,
+
+    /// Publish a Kubernetes Event on each workload when tracking concludes
+    #[clap(long)]
+    events: bool,
+
+    /// Annotate each workload with the last tracked revision and its outcome
+    #[clap(long)]
+    annotate: bool,
+
+    /// What to do when a newer apply replaces a tracked revision
+    #[clap(long, value_enum, default_value_t = SupersedePolicy::Follow)]
+    on_supersede: SupersedePolicy,
+
+    /// Print CI native output (detected from the environment by default)
+    #[clap(long, value_enum)]
+    ci: Option<Ci>,
+
+    /// How to render progress (bars on terminals and plain lines otherwise by default)
+    #[clap(long, value_enum)]
+    progress: Option<Render>,
+
+    /// Write a JUnit XML report with a testcase per workload to this file
+    #[clap(long)]
+    junit: Option<std::path::PathBuf>,
+
+    /// Record every object fetched while tracking to this file (for irt replay)
+    #[clap(long)]
+    record: Option<PathBuf>,
+
+    /// Serve prometheus metrics on this address while tracking
+    #[cfg(feature = "metrics")]
+    #[clap(long)]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Write prometheus metrics to this file after tracking (for node-exporter)
+    #[cfg(feature = "metrics")]
+    #[clap(long)]
+    metrics_textfile: Option<std::path::PathBuf>,
+
+    /// Webhook url(s) to POST rollout start, finish and failure notifications to
+    #[cfg(feature = "notify")]
+    #[clap(long)]
+    webhook: Vec<String>,
+
+    /// Payload format for webhooks
+    #[cfg(feature = "notify")]
+    #[clap(long, value_enum, default_value_t = WebhookFormat::Generic)]
+    webhook_format: WebhookFormat,
+}
+
+#[cfg(feature = "notify")]
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum WebhookFormat {
+    Generic,
+    Slack,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct StatusArgs {
+    /// Comma-separated list of workloads to show
+    #[clap(long, short = 'w', use_value_delimiter = true, value_parser = Workload::from_str, add = ArgValueCompleter::new(complete_workloads))]
+    workloads: Vec<Workload>,
+
+    /// The namespace to use for all workloads
+    #[clap(short = 'n', long, add = ArgValueCompleter::new(complete_namespaces))]
+    namespace: Option<String>,
+
+    /// Output format
+    #[clap(short = 'o', long, value_enum, default_value_t = Output::Table)]
+    output: Output,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct DiagnoseArgs {
+    /// The workload to diagnose
+    ///
+    /// Example: monitoring/deploy/grafana
+    #[clap(value_parser = Workload::from_str, add = ArgValueCompleter::new(complete_workloads))]
+    workload: Workload,
+
+    /// The namespace of the workload (if not set in the workload)
+    #[clap(short = 'n', long, add = ArgValueCompleter::new(complete_namespaces))]
+    namespace: Option<String>,
+
+    /// Number of log lines to show from each failing container
+    #[clap(long, default_value_t = 30)]
+    log_lines: i64,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct HistoryArgs {
+    /// The workload to show revisions for
+    ///
+    /// Example: monitoring/deploy/grafana
+    #[clap(value_parser = Workload::from_str, add = ArgValueCompleter::new(complete_workloads))]
+    workload: Workload,
+
+    /// The namespace of the workload (if not set in the workload)
+    #[clap(short = 'n', long, add = ArgValueCompleter::new(complete_namespaces))]
     namespace: Option<String>,
+
+    /// Output format
+    #[clap(short = 'o', long, value_enum, default_value_t = Output::Table)]
+    output: Output,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct ReplayArgs {
+    /// The session file written by track --record
+    ///
+    /// Every rollout in the session is tracked again from the recorded objects, with the
+    /// parameters it was tracked with, and diagnosed if it failed.
+    session: PathBuf,
+
+    /// Number of log lines to show from each failing container
+    #[clap(long, default_value_t = 30)]
+    log_lines: i64,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct ServeArgs {
+    /// Comma-separated list of namespaces to watch (default all)
+    #[clap(short = 'n', long, use_value_delimiter = true)]
+    namespaces: Vec<String>,
+
+    /// Serve the rollout api on this address
+    #[cfg(feature = "server")]
+    #[clap(long)]
+    addr: Option<std::net::SocketAddr>,
 }
 
 #[tokio::main]
@@ -70,31 +332,621 @@ async fn main() -> anyhow::Result<()> {
     unsafe {
         libc::signal(libc::SIGPIPE, libc::SIG_DFL);
     }
+    clap_complete::CompleteEnv::with_factory(Irt::command).complete();
     let cli = <Irt as clap::Parser>::parse();
-    match cli.command {
-        Command::Track(args) => handle_track(args).await?,
+    // warnings from the library explain degraded tracking, progress is reported by irt itself
+    let level = match cli.command {
+        Command::Diagnose(_) | Command::Serve(_) => tracing::Level::INFO,
+        _ => tracing::Level::WARN,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+    let command = match cli.command {
+        Command::Completions { shell } => {
+            let shells = clap_complete::env::Shells::builtins();
+            let completer = shells.completer(&shell.to_string()).expect("builtin shell");
+            completer.write_registration("COMPLETE", "irt", "irt", "irt", &mut std::io::stdout())?;
+            return Ok(());
+        }
+        Command::Track(args) => return handle_track(args, &cli.kube).await,
+        Command::Replay(args) => return handle_replay(args).await,
+        command => command,
+    };
+    let client = cli.kube.client().await?;
+    match command {
+        Command::Status(args) => handle_status(args, client).await?,
+        Command::Diagnose(args) => handle_diagnose(args, client).await?,
+        Command::History(args) => handle_history(args, client).await?,
+        Command::Serve(args) => handle_serve(args, client).await?,
+        Command::Track(_) | Command::Replay(_) | Command::Completions { .. } => {
+            unreachable!("handled above")
+        }
     }
     Ok(())
 }
 
-async fn handle_track(args: TrackArgs) -> Result<()> {
-    let client = kube::Client::try_default().await.unwrap();
-    for wl in args.workloads {
-        let (kind, name, ns) = match wl {
-            Workload::Deployment(name, ns) => (Kind::Deployment, name, ns),
-            Workload::StatefulSet(name, ns) => (Kind::StatefulSet, name, ns),
-            Workload::DaemonSet(name, ns) => (Kind::DaemonSet, name, ns),
+async fn handle_track(args: TrackArgs, kube: &KubeArgs) -> anyhow::Result<()> {
+    let config = args.config.as_deref().map(Config::from_file).transpose()?;
+    let mut stages = vec![];
+    if !args.workloads.is_empty() {
+        let workloads = args.workloads.iter().map(|t| WorkloadConfig {
+            version: args.expect_version.clone(),
+            version_label: args.version_label.clone(),
+            version_scheme: args.version_scheme,
+            ..t.clone().into()
+        });
+        stages.push(Group {
+            name: "workloads".into(),
+            workloads: workloads.collect(),
+        });
+    }
+    stages.extend(config.iter().flat_map(Config::stages));
+    if stages.is_empty() {
+        anyhow::bail!("no workloads to track; use --workloads or --config");
+    }
+    let namespace = args
+        .namespace
+        .clone()
+        .or_else(|| config.as_ref()?.namespace.clone());
+    let recorder = args.record.as_deref().map(Recorder::create).transpose()?;
+    let mut clients = BTreeMap::new();
+    let mut planned = vec![];
+    for stage in &stages {
+        let mut rollouts = vec![];
+        for wl in &stage.workloads {
+            let contexts = match (&wl.workload.context, kube.context.as_slice()) {
+                (Some(ctx), _) => vec![Some(ctx.clone())],
+                (None, []) => vec![None],
+                (None, contexts) => contexts.iter().cloned().map(Some).collect(),
+            };
+            for ctx in contexts {
+                let client = match clients.get(&ctx) {
+                    Some(client) => client,
+                    None => {
+                        let mut client = kube.client_for(ctx.as_deref()).await?;
+                        if let Some(recorder) = &recorder {
+                            client = recorder.client(client, ctx.as_deref());
+                        }
+                        clients.entry(ctx.clone()).or_insert(client)
+                    }
+                };
+                let r = wl.rollout(client.clone());
+                let r = Rollout {
+                    namespace: r.namespace.clone().or_else(|| namespace.clone()),
+                    on_supersede: args.on_supersede,
+                    cluster: ctx,
+                    ..r
+                };
+                if let Some(recorder) = &recorder {
+                    recorder.track(&r);
+                }
+                rollouts.push((r, wl));
+            }
+        }
+        planned.push((&stage.name, rollouts));
+    }
+    let ci = args.ci.or_else(Ci::detect);
+    let tracker = Tracker {
+        progress: match (args.progress, ci) {
+            (Some(render), _) => Progress::new(render),
+            (None, Some(ci)) => ci.progress(),
+            (None, None) => Progress::new(Render::detect()),
+        },
+        ci,
+        #[cfg(feature = "metrics")]
+        metrics: irae::metrics::Metrics::default(),
+        #[cfg(feature = "notify")]
+        notifier: {
+            use irae::notify::{Format, Notifier, Webhook};
+            let format = match args.webhook_format {
+                WebhookFormat::Generic => Format::Generic,
+                WebhookFormat::Slack => Format::Slack,
+            };
+            let hooks = args.webhook.iter().map(|url| Webhook {
+                format: format.clone(),
+                ..Webhook::new(url)
+            });
+            let configured = config.iter().flat_map(|c| c.notify.clone());
+            Notifier::new(hooks.chain(configured).collect())
+        },
+        args: &args,
+    };
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = args.metrics_addr {
+        // bound here so a port in use fails the run instead of a background task
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        let metrics = tracker.metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics.serve(listener).await {
+                tracing::error!("Metrics server stopped: {e}");
+            }
+        });
+    }
+    let reports = run_stages(
+        planned,
+        |(r, _)| r.workload_ref(),
+        async |(r, wl)| tracker.track(r, wl).await,
+    )
+    .await;
+    if reports.len() > 1 {
+        tracker
+            .progress
+            .suspend(|| print!("{}", irae::term::stages_table(&reports)));
+    }
+    let results = reports.into_iter().flat_map(|s| s.tracked).collect::<Vec<_>>();
+    if let Some(ci) = ci {
+        ci.summary(&results)?;
+    }
+    if let Some(path) = &args.junit {
+        irae::term::junit::write(path, &results)?;
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(path) = &args.metrics_textfile {
+        tracker.metrics.write_textfile(path)?;
+    }
+    check(&results)
+}
+
+/// Track the workloads of each stage concurrently
+///
+/// Stages run in order, and a failing stage skips the rest.
+async fn run_stages<W>(
+    planned: Vec<(&String, Vec<W>)>,
+    workload: impl Fn(&W) -> String,
+    track: impl AsyncFn(&W) -> Tracked,
+) -> Vec<Stage> {
+    let mut reports: Vec<Stage> = vec![];
+    for (name, rollouts) in planned {
+        let tracked = if reports.iter().all(Stage::is_success) {
+            futures::future::join_all(rollouts.iter().map(|w| track(w))).await
+        } else {
+            vec![]
         };
-        let r = Rollout {
-            name,
-            namespace: ns.or_else(|| args.namespace.clone()),
-            workload: kind,
-            client: client.clone(),
+        reports.push(Stage {
+            name: name.clone(),
+            workloads: rollouts.iter().map(&workload).collect(),
+            tracked,
+        });
+    }
+    reports
+}
+
+/// Fail unless every tracked rollout succeeded (or was unchanged)
+fn check(results: &[Tracked]) -> anyhow::Result<()> {
+    let failed = results
+        .iter()
+        .filter(|t| !t.is_success())
+        .map(|t| format!("{} {}", t.workload, t.outcome()))
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        anyhow::bail!("unsuccessful rollouts: {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+/// Integrations shared between concurrently tracked rollouts
+struct Tracker<'a> {
+    args: &'a TrackArgs,
+    progress: Progress,
+    ci: Option<Ci>,
+    #[cfg(feature = "metrics")]
+    metrics: irae::metrics::Metrics,
+    #[cfg(feature = "notify")]
+    notifier: irae::notify::Notifier,
+}
+
+impl Tracker<'_> {
+    /// Track a rollout and report how it concluded
+    ///
+    /// Rollouts that could not be tracked are reported in the result rather than as errors.
+    /// Events and annotations are best effort, and failing to publish them only prints a warning.
+    async fn track(&self, r: &Rollout, wl: &WorkloadConfig) -> Tracked {
+        let (args, ci) = (self.args, self.ci);
+        let workload = r.workload_ref();
+        let start = std::time::Instant::now();
+        #[cfg(feature = "metrics")]
+        self.metrics.started(r);
+        #[cfg(feature = "notify")]
+        self.notifier
+            .notify(&irae::notify::Notification::started(r))
+            .await;
+        let tracked = self.progress.track(r).await;
+        #[cfg(feature = "metrics")]
+        match &tracked {
+            Ok((verdict, _)) => self.metrics.finished(r, verdict, start.elapsed()),
+            Err(e) => self.metrics.failed(r, e),
+        }
+        let (verdict, state) = match tracked {
+            Ok(res) => res,
+            Err(e) => {
+                if args.events {
+                    if let Err(err) = r.publish_error(&e, start.elapsed()).await {
+                        self.warn(format!("Unable to publish event for {workload}: {err}"));
+                    }
+                }
+                #[cfg(feature = "notify")]
+                self.notifier
+                    .notify(&irae::notify::Notification::errored(r, &e, start.elapsed()))
+                    .await;
+                if let Some(ci) = ci {
+                    ci.error(&workload, &e.to_string());
+                }
+                return Tracked {
+                    workload,
+                    version: None,
+                    duration: start.elapsed(),
+                    result: Err(e.to_string()),
+                    diagnosis: None,
+                };
+            }
+        };
+        let duration = start.elapsed();
+        if args.events {
+            if let Err(e) = r.publish_verdict(&verdict, duration).await {
+                self.warn(format!("Unable to publish event for {workload}: {e}"));
+            }
+        }
+        if args.annotate {
+            if let Err(e) = r.annotate_verdict(&state, &verdict, duration).await {
+                self.warn(format!("Unable to annotate {workload}: {e}"));
+            }
+        }
+        let diagnosis = if verdict.needs_diagnosis() {
+            match r.diagnose(&state, args.log_lines).await {
+                Ok(diag) => {
+                    // printed in one go so concurrent rollouts do not interleave
+                    self.progress.suspend(|| {
+                        let title = format!("Debugging {workload}");
+                        ci.inspect(|ci| ci.group_start(&title));
+                        diag.print();
+                        ci.inspect(|ci| ci.group_end(&title));
+                    });
+                    Some(diag)
+                }
+                Err(e) => {
+                    self.warn(format!("Unable to diagnose {workload}: {e}"));
+                    None
+                }
+            }
+        } else {
+            None
         };
-        let (outcome, state) = irae::term::workload_rollout(&r).await?;
-        if !outcome {
-            r.debug(&state).await?;
+        // rolled back whether or not the diagnosis worked
+        if verdict == Verdict::TimedOut && wl.rollback == Some(RollbackPolicy::Undo) {
+            let undone = match r.undo().await {
+                Ok(Some(rev)) => format!("Rolled back {workload} to revision {}", rev.revision),
+                Ok(None) => format!("Unable to roll back {workload}: no earlier revision"),
+                Err(e) => format!("Unable to roll back {workload}: {e}"),
+            };
+            self.progress.suspend(|| println!("{undone}"));
+        }
+        if let (Some(ci), false) = (ci, verdict.is_success()) {
+            let detail = diagnosis.as_ref().map(|d| d.summary());
+            ci.error(
+                &format!("{workload} {verdict}"),
+                &detail.unwrap_or(verdict.to_string()),
+            );
+        }
+        let version = r.tracked_version(&state).await.unwrap_or_default();
+        #[cfg(feature = "notify")]
+        {
+            let mut n = irae::notify::Notification::finished(r, &verdict, duration);
+            n.version = version.clone();
+            n.diagnosis = diagnosis.as_ref().map(|d| d.summary());
+            self.notifier.notify(&n).await;
+        }
+        Tracked {
+            workload,
+            version,
+            duration,
+            result: Ok(verdict),
+            diagnosis,
+        }
+    }
+
+    /// Print a warning without garbling progress bars
+    fn warn(&self, msg: String) {
+        self.progress.suspend(|| eprintln!("{msg}"));
+    }
+}
+
+/// Track the rollouts of a recorded session again, with the parameters they were tracked with
+async fn handle_replay(args: ReplayArgs) -> anyhow::Result<()> {
+    let jsonl = std::fs::read_to_string(&args.session)?;
+    let (rollouts, session) = (irae::replay::rollouts(&jsonl)?, irae::replay::parse(&jsonl)?);
+    if rollouts.is_empty() {
+        anyhow::bail!("no rollouts were tracked in the session");
+    }
+    let progress = Progress::periodic();
+    for tracking in rollouts {
+        let context = tracking.workload.parse::<Target>()?.context;
+        let exchanges = session.iter().filter(|e| e.context == context);
+        let replay = Replay::session(exchanges.cloned().collect());
+        let r = replay.rollout(&tracking)?;
+        let (verdict, state) = progress.track(&r).await?;
+        if verdict.needs_diagnosis() {
+            match r.diagnose(&state, args.log_lines).await {
+                Ok(diag) => diag.print(),
+                Err(e) => println!("Unable to diagnose {}: {e}", r.workload_ref()),
+            }
         }
     }
     Ok(())
 }
+
+async fn handle_status(args: StatusArgs, client: kube::Client) -> Result<()> {
+    let mut snapshots = vec![];
+    for wl in args.workloads {
+        snapshots.push(wl.rollout(&args.namespace, &client).snapshot().await?);
+    }
+    match args.output {
+        Output::Table => print!("{}", irae::term::status_table(&snapshots)),
+        Output::Json => {
+            let json = serde_json::to_string_pretty(&snapshots).map_err(irae::Error::Serialization)?;
+            println!("{json}");
+        }
+    }
+    Ok(())
+}
+
+async fn handle_diagnose(args: DiagnoseArgs, client: kube::Client) -> Result<()> {
+    let r = args.workload.rollout(&args.namespace, &client);
+    let state = r.current_state().await?;
+    r.debug(&state, args.log_lines).await?;
+    Ok(())
+}
+
+async fn handle_history(args: HistoryArgs, client: kube::Client) -> Result<()> {
+    let revisions = args.workload.rollout(&args.namespace, &client).history().await?;
+    match args.output {
+        Output::Table => print!("{}", irae::term::history_table(&revisions)),
+        Output::Json => {
+            let json = serde_json::to_string_pretty(&revisions).map_err(irae::Error::Serialization)?;
+            println!("{json}");
+        }
+    }
+    Ok(())
+}
+
+async fn handle_serve(args: ServeArgs, client: kube::Client) -> Result<()> {
+    let watcher = irae::watch::Watcher::new(client, args.namespaces);
+    #[cfg(feature = "server")]
+    if let Some(addr) = args.addr {
+        // bound here so a port in use fails at startup instead of in a background task
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(irae::Error::Io)?;
+        let store = watcher.store();
+        tokio::spawn(async move {
+            if let Err(e) = irae::server::serve(store, listener).await {
+                tracing::error!("Rollout api stopped: {e}");
+            }
+        });
+    }
+    watcher.run().await
+}
+
+// ----------------------------------------------------------------------------
+// dynamic shell completion (from the current kubeconfig context)
+
+const KINDS: [&str; 4] = ["deploy", "sts", "ds", "job"];
+
+/// Complete `[ns/]kind/name` workloads, including the last of a comma-separated list
+fn complete_workloads(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    let (mut done, mut partial) = match current.rsplit_once(',') {
+        Some((done, partial)) => (format!("{done},"), partial.to_string()),
+        None => (String::new(), current.to_string()),
+    };
+    let mut context = None;
+    // contexts may contain colons (eks arns), but workloads never do
+    if let Some((ctx, rest)) = partial.clone().rsplit_once(':') {
+        done += &format!("{ctx}:");
+        partial = rest.to_string();
+        context = Some(ctx.to_string());
+    }
+    let mut candidates = vec![];
+    if !partial.contains('/') {
+        candidates.extend(KINDS.map(|k| format!("{k}/")));
+    }
+    candidates.extend(from_cluster(context, {
+        let partial = partial.clone();
+        move |client, namespace| async move { workload_candidates(client, &partial, namespace).await }
+    }));
+    candidates
+        .into_iter()
+        .filter(|c| c.starts_with(&partial))
+        .map(|c| CompletionCandidate::new(format!("{done}{c}")))
+        .collect()
+}
+
+fn complete_namespaces(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy().to_string();
+    from_cluster(None, |client, _| async move { namespaces(client).await })
+        .into_iter()
+        .filter(|ns| ns.starts_with(&current))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+async fn workload_candidates(
+    client: kube::Client,
+    partial: &str,
+    namespace: Option<String>,
+) -> anyhow::Result<Vec<String>> {
+    let candidates = match partial.split('/').collect::<Vec<_>>().as_slice() {
+        [_] => namespaces(client)
+            .await?
+            .into_iter()
+            .map(|ns| format!("{ns}/"))
+            .collect(),
+        [kind, _] if Kind::from_str(kind).is_ok() => {
+            let names = workload_names(client, namespace.as_deref(), kind).await?;
+            names.into_iter().map(|n| format!("{kind}/{n}")).collect()
+        }
+        [ns, _] => KINDS.map(|k| format!("{ns}/{k}/")).to_vec(),
+        [ns, kind, _] => {
+            let names = workload_names(client, Some(ns), kind).await?;
+            names.into_iter().map(|n| format!("{ns}/{kind}/{n}")).collect()
+        }
+        _ => vec![],
+    };
+    Ok(candidates)
+}
+
+async fn namespaces(client: kube::Client) -> anyhow::Result<Vec<String>> {
+    use k8s_openapi::api::core::v1::Namespace;
+    let list = kube::Api::<Namespace>::all(client)
+        .list_metadata(&Default::default())
+        .await?;
+    Ok(list.items.into_iter().filter_map(|ns| ns.metadata.name).collect())
+}
+
+async fn workload_names(client: kube::Client, ns: Option<&str>, kind: &str) -> anyhow::Result<Vec<String>> {
+    use k8s_openapi::api::{
+        apps::v1::{DaemonSet, Deployment, StatefulSet},
+        batch::v1::Job,
+    };
+    async fn names<K>(client: kube::Client, ns: Option<&str>) -> anyhow::Result<Vec<String>>
+    where
+        K: kube::Resource<Scope = k8s_openapi::NamespaceResourceScope, DynamicType = ()>,
+        K: Clone + serde::de::DeserializeOwned + std::fmt::Debug,
+    {
+        let api = match ns {
+            Some(ns) => kube::Api::<K>::namespaced(client, ns),
+            None => kube::Api::<K>::default_namespaced(client),
+        };
+        let list = api.list_metadata(&Default::default()).await?;
+        Ok(list.items.into_iter().filter_map(|o| o.metadata.name).collect())
+    }
+    match Kind::from_str(kind)? {
+        Kind::Deployment => names::<Deployment>(client, ns).await,
+        Kind::StatefulSet => names::<StatefulSet>(client, ns).await,
+        Kind::DaemonSet => names::<DaemonSet>(client, ns).await,
+        Kind::Job => names::<Job>(client, ns).await,
+    }
+}
+
+/// Run a cluster query for completion on its own thread (completion runs inside the main runtime)
+///
+/// The query gets a client for the given context (or the typed --context and --kubeconfig),
+/// and the typed --namespace. Failures yield no candidates rather than noise in the shell.
+fn from_cluster<F, Fut>(context: Option<String>, query: F) -> Vec<String>
+where
+    F: FnOnce(kube::Client, Option<String>) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = anyhow::Result<Vec<String>>>,
+{
+    let (kube, namespace) = typed_flags(std::env::args());
+    let context = context.or_else(|| kube.context.first().cloned());
+    let thread = std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        rt.block_on(async move { query(kube.client_for(context.as_deref()).await?, namespace).await })
+    });
+    thread.join().ok().and_then(|res| res.ok()).unwrap_or_default()
+}
+
+/// Cluster flags typed on a command line that is being completed
+///
+/// Completers only see the value being completed, so these are picked from the raw arguments.
+fn typed_flags(args: impl IntoIterator<Item = String>) -> (KubeArgs, Option<String>) {
+    let mut kube = KubeArgs {
+        context: vec![],
+        kubeconfig: None,
+    };
+    let mut namespace = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = || inline.clone().or_else(|| args.next());
+        match flag.as_str() {
+            "--context" => kube.context.extend(value()),
+            "--kubeconfig" => kube.kubeconfig = value().map(PathBuf::from),
+            "-n" | "--namespace" => namespace = value(),
+            short if short.starts_with("-n") => namespace = Some(short[2..].to_string()),
+            _ => {}
+        }
+    }
+    (kube, namespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_typed_cluster_flags() {
+        let args = "irt -- irt --context=prod --kubeconfig /tmp/kc track -nshop -w deploy/";
+        let (kube, namespace) = typed_flags(args.split(' ').map(String::from));
+        assert_eq!(kube.context, ["prod"]);
+        assert_eq!(kube.kubeconfig, Some(PathBuf::from("/tmp/kc")));
+        assert_eq!(namespace.as_deref(), Some("shop"));
+        let (_, namespace) = typed_flags(["irt", "status", "--namespace", "kube-system"].map(String::from));
+        assert_eq!(namespace.as_deref(), Some("kube-system"));
+    }
+
+    fn tracked(workload: &str, result: std::result::Result<Verdict, String>) -> Tracked {
+        Tracked {
+            workload: workload.into(),
+            version: None,
+            duration: std::time::Duration::from_secs(5),
+            result,
+            diagnosis: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_stages_after_a_failure() {
+        let names = ["db", "api", "web"].map(String::from);
+        let planned = vec![
+            (&names[0], vec!["shop/StatefulSet/db"]),
+            (&names[1], vec!["shop/Deployment/api", "shop/Job/migrate"]),
+            (&names[2], vec!["shop/Deployment/web"]),
+        ];
+        let calls = std::sync::Mutex::new(vec![]);
+        let track = async |wl: &&str| {
+            calls.lock().unwrap().push(wl.to_string());
+            match *wl {
+                "shop/Job/migrate" => tracked(wl, Ok(Verdict::Failed("BackoffLimitExceeded".into()))),
+                _ => tracked(wl, Ok(Verdict::Succeeded)),
+            }
+        };
+        let stages = run_stages(planned, |wl| wl.to_string(), track).await;
+        let outcomes = stages.iter().map(Stage::outcome).collect::<Vec<_>>();
+        assert_eq!(outcomes, ["succeeded", "failed", "skipped"]);
+        assert_eq!(stages[2].workloads, ["shop/Deployment/web"]);
+        assert!(!calls
+            .into_inner()
+            .unwrap()
+            .contains(&"shop/Deployment/web".to_string()));
+    }
+
+    #[test]
+    fn fails_unless_every_rollout_succeeded() {
+        let ok = [
+            tracked("shop/Deployment/web", Ok(Verdict::Succeeded)),
+            tracked("shop/StatefulSet/db", Ok(Verdict::Unchanged)),
+        ];
+        assert!(check(&ok).is_ok());
+        let unsuccessful = [
+            Ok(Verdict::TimedOut),
+            Ok(Verdict::WrongVersion(vec!["web-1 runs 1.2.0".into()])),
+            Ok(Verdict::Failed("crashloop".into())),
+            Err("forbidden".into()),
+        ];
+        for result in unsuccessful {
+            let outcome = tracked("shop/Job/migrate", result);
+            let err = check(&[ok[0].clone(), outcome.clone()]).unwrap_err().to_string();
+            assert_eq!(
+                err,
+                format!("unsuccessful rollouts: shop/Job/migrate {}", outcome.outcome())
+            );
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! building rollouts from names or from objects in the cluster
+use crate::{Error, Kind, PollStrategy, Result, Rollout, SupersedePolicy, VersionScheme, Versioning};
+
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, Deployment, StatefulSet},
+    batch::v1::Job,
+    core::v1::ObjectReference,
+};
+use kube::{Resource, ResourceExt};
+use std::time::Duration;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Builder for a `Rollout` from `Rollout::builder`
+pub struct RolloutBuilder {
+    rollout: Rollout,
+}
+
+impl Rollout {
+    /// Build a rollout of a workload, checked against the cluster by `RolloutBuilder::build`
+    pub fn builder(client: kube::Client, kind: Kind, name: impl Into<String>) -> RolloutBuilder {
+        RolloutBuilder {
+            rollout: Rollout {
+                name: name.into(),
+                namespace: None,
+                workload: kind,
+                on_supersede: SupersedePolicy::default(),
+                timeout: None,
+                container: None,
+                expect_version: None,
+                versioning: Versioning::default(),
+                poll: PollStrategy::default(),
+                client,
+                cluster: None,
+            },
+        }
+    }
+
+    /// A rollout of a workload object that was already fetched
+    pub fn from_resource<K>(client: kube::Client, obj: &K) -> Result<Rollout>
+    where
+        K: Resource<DynamicType = ()>,
+    {
+        let kind = K::kind(&()).parse()?;
+        let builder = Rollout::builder(client, kind, obj.name_any());
+        match obj.namespace() {
+            Some(ns) => Ok(builder.namespace(ns).rollout),
+            None => Ok(builder.rollout),
+        }
+    }
+
+    /// A rollout of the workload an object reference points to (e.g. the involved object of an event)
+    pub fn from_object_ref(client: kube::Client, reference: &ObjectReference) -> Result<Rollout> {
+        let (Some(kind), Some(name)) = (&reference.kind, &reference.name) else {
+            return Err(Error::InvalidReference(
+                "object reference needs a kind and a name".into(),
+            ));
+        };
+        let builder = Rollout::builder(client, kind.parse()?, name);
+        match &reference.namespace {
+            Some(ns) => Ok(builder.namespace(ns).rollout),
+            None => Ok(builder.rollout),
+        }
+    }
+
+    /// Check that the workload exists as the kind it is tracked as
+    ///
+    /// Names of other kinds are looked up to point out a mismatching kind.
+    pub async fn validate(&self) -> Result<()> {
+        if self.exists(&self.workload).await? {
+            return Ok(());
+        }
+        let workload = self.workload_ref();
+        for kind in [Kind::Deployment, Kind::StatefulSet, Kind::DaemonSet, Kind::Job] {
+            if kind != self.workload && self.exists(&kind).await? {
+                let msg = format!("{workload} (but found a {kind} named {})", self.name);
+                return Err(Error::WorkloadNotFound(msg));
+            }
+        }
+        Err(Error::WorkloadNotFound(workload))
+    }
+
+    async fn exists(&self, kind: &Kind) -> Result<bool> {
+        let found = match kind {
+            Kind::Deployment => self
+                .ns::<Deployment>()
+                .get_opt(&self.name)
+                .await
+                .map(|o| o.is_some()),
+            Kind::StatefulSet => self
+                .ns::<StatefulSet>()
+                .get_opt(&self.name)
+                .await
+                .map(|o| o.is_some()),
+            Kind::DaemonSet => self
+                .ns::<DaemonSet>()
+                .get_opt(&self.name)
+                .await
+                .map(|o| o.is_some()),
+            Kind::Job => self.ns::<Job>().get_opt(&self.name).await.map(|o| o.is_some()),
+        };
+        found.map_err(Error::Kube)
+    }
+}
+
+impl RolloutBuilder {
+    /// Namespace of the workload (the context namespace by default)
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.rollout.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Name of the main container
+    pub fn container(mut self, container: impl Into<String>) -> Self {
+        self.rollout.container = Some(container.into());
+        self
+    }
+
+    /// Version (image tag) or image digest the ready pods must run
+    pub fn expect_version(mut self, version: impl Into<String>) -> Self {
+        self.rollout.expect_version = Some(version.into());
+        self
+    }
+
+    /// Label with the version of a revision
+    pub fn version_label(mut self, label: impl Into<String>) -> Self {
+        self.rollout.versioning.label = label.into();
+        self
+    }
+
+    /// How versions are ordered to find the newest revision
+    pub fn version_scheme(mut self, scheme: VersionScheme) -> Self {
+        self.rollout.versioning.scheme = scheme;
+        self
+    }
+
+    /// Maximum time to wait for the rollout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.rollout.timeout = Some(timeout);
+        self
+    }
+
+    /// How often to poll the status while tracking
+    pub fn poll(mut self, poll: PollStrategy) -> Self {
+        self.rollout.poll = poll;
+        self
+    }
+
+    /// What to do when a newer revision replaces the one being tracked
+    pub fn on_supersede(mut self, policy: SupersedePolicy) -> Self {
+        self.rollout.on_supersede = policy;
+        self
+    }
+
+    /// Name of the cluster the client talks to, for labelling output
+    pub fn cluster(mut self, cluster: impl Into<String>) -> Self {
+        self.rollout.cluster = Some(cluster.into());
+        self
+    }
+
+    /// The rollout, after checking that the workload exists as the given kind
+    pub async fn build(self) -> Result<Rollout> {
+        self.rollout.validate().await?;
+        Ok(self.rollout)
+    }
+}
+
+#[cfg(all(test, feature = "replay"))]
+mod tests {
+    use super::*;
+    use crate::replay::{parse, Replay};
+
+    #[tokio::test]
+    async fn builds_existing_workloads() {
+        let replay = Replay::new(parse(include_str!("../fixtures/deployment.jsonl")).unwrap());
+        let client = replay.client("shop");
+        let r = Rollout::builder(client.clone(), Kind::Deployment, "web")
+            .container("app")
+            .timeout(Duration::from_secs(60))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(r.workload_ref(), "shop/Deployment/web");
+
+        let wrong = Rollout::builder(client.clone(), Kind::StatefulSet, "web")
+            .build()
+            .await;
+        let err = wrong.err().unwrap().to_string();
+        assert_eq!(
+            err,
+            "Workload not found: shop/StatefulSet/web (but found a Deployment named web)"
+        );
+        let missing = Rollout::builder(client.clone(), Kind::Deployment, "api")
+            .build()
+            .await;
+        assert!(missing.is_err());
+
+        let deploy = r.get_deploy().await.unwrap();
+        let r = Rollout::from_resource(client.clone(), &deploy).unwrap();
+        assert_eq!(r.workload_ref(), "shop/Deployment/web");
+        let reference = ObjectReference {
+            kind: Some("StatefulSet".into()),
+            name: Some("db".into()),
+            namespace: Some("shop".into()),
+            ..Default::default()
+        };
+        let r = Rollout::from_object_ref(client, &reference).unwrap();
+        assert_eq!(r.workload_ref(), "shop/StatefulSet/db");
+    }
+}
@@ -0,0 +1,429 @@
+//! declarative tracking configuration (an `irae.yaml`)
+//!
+//! ```yaml
+//! namespace: payments
+//! timeout: 10m
+//! rollback: undo
+//! version_scheme: calver # or semver (default), created (e.g. git shas)
+//! notify: # with the notify feature
+//!   - url: https://hooks.slack.com/services/T000/B000/XXX
+//!     format: slack
+//! groups: # tracked in order, each after the previous succeeded
+//!   - name: migrate
+//!     workloads:
+//!       - workload: job/migrate
+//!   - name: backend
+//!     workloads:
+//!       - workload: deploy/api
+//!         version: 1.4.2
+//!         container: api
+//!       - workload: prod-eu:payments/sts/ledger
+//!         timeout: 20m
+//!         rollback: never
+//!         version_label: ledger.example.com/release
+//!         version_scheme: created
+//! ```
+use crate::{Error, Kind, Result, RollbackPolicy, Rollout, SupersedePolicy, VersionScheme, Versioning};
+
+use serde::{Deserialize, Deserializer};
+use std::{collections::BTreeSet, path::Path, str::FromStr, time::Duration};
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Shortest timeout a workload can be tracked with (rollouts need a few polls)
+const MIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tracking configuration for groups of workloads
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Namespace for workloads that do not set one
+    pub namespace: Option<String>,
+    /// Default timeout for each workload (estimated from the workload if unset)
+    #[serde(default, deserialize_with = "duration")]
+    pub timeout: Option<Duration>,
+    /// Default policy for workloads that time out
+    #[serde(default)]
+    pub rollback: RollbackPolicy,
+    /// Default label with the version of a revision (app.kubernetes.io/version if unset)
+    pub version_label: Option<String>,
+    /// Default ordering of versions
+    pub version_scheme: Option<VersionScheme>,
+    /// Webhooks to notify about rollouts
+    #[cfg(feature = "notify")]
+    #[serde(default)]
+    pub notify: Vec<crate::notify::Webhook>,
+    /// Webhooks to notify about rollouts (rejected without the notify feature)
+    #[cfg(not(feature = "notify"))]
+    #[serde(default)]
+    pub notify: Vec<serde_yaml::Value>,
+    /// Named groups of workloads
+    pub groups: Vec<Group>,
+}
+
+/// A named group of workloads that roll out together
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    pub name: String,
+    pub workloads: Vec<WorkloadConfig>,
+}
+
+/// A workload with its tracking settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkloadConfig {
+    pub workload: Target,
+    /// Maximum time to wait for the rollout
+    #[serde(default, deserialize_with = "duration")]
+    pub timeout: Option<Duration>,
+    /// Version the rollout is expected to end up on
+    pub version: Option<String>,
+    /// Name of the main container
+    pub container: Option<String>,
+    /// What to do if the rollout times out
+    pub rollback: Option<RollbackPolicy>,
+    /// Label with the version of a revision
+    pub version_label: Option<String>,
+    /// How versions are ordered to find the newest revision
+    pub version_scheme: Option<VersionScheme>,
+}
+
+impl From<Target> for WorkloadConfig {
+    fn from(workload: Target) -> Self {
+        WorkloadConfig {
+            workload,
+            timeout: None,
+            version: None,
+            container: None,
+            rollback: None,
+            version_label: None,
+            version_scheme: None,
+        }
+    }
+}
+
+impl WorkloadConfig {
+    /// The version label and scheme, defaulting to semver in app.kubernetes.io/version
+    pub fn versioning(&self) -> Versioning {
+        let default = Versioning::default();
+        Versioning {
+            label: self.version_label.clone().unwrap_or(default.label),
+            scheme: self.version_scheme.unwrap_or(default.scheme),
+        }
+    }
+
+    /// A rollout of the workload through a client for its context
+    pub fn rollout(&self, client: kube::Client) -> Rollout {
+        let target = &self.workload;
+        Rollout {
+            name: target.name.clone(),
+            namespace: target.namespace.clone(),
+            workload: target.kind.clone(),
+            on_supersede: SupersedePolicy::default(),
+            client,
+            cluster: target.context.clone(),
+            timeout: self.timeout,
+            container: self.container.clone(),
+            expect_version: self.version.clone(),
+            versioning: self.versioning(),
+            poll: Default::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Read and validate a config file
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("unable to read {}: {e}", path.display())))?;
+        data.parse()
+    }
+
+    /// Check the config for mistakes that the file format cannot express
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: String| Err(Error::InvalidConfig(msg));
+        if self.groups.is_empty() {
+            return invalid("no groups of workloads".into());
+        }
+        #[cfg(not(feature = "notify"))]
+        if !self.notify.is_empty() {
+            return invalid("notify requires the notify feature".into());
+        }
+        let mut groups = BTreeSet::new();
+        let mut workloads: Vec<Target> = vec![];
+        for group in &self.groups {
+            if group.name.is_empty() {
+                return invalid("group without a name".into());
+            }
+            if !groups.insert(&group.name) {
+                return invalid(format!("group {} is defined more than once", group.name));
+            }
+            if group.workloads.is_empty() {
+                return invalid(format!("group {} has no workloads", group.name));
+            }
+            for wl in &group.workloads {
+                let target = wl.workload.to_string();
+                // workloads without a namespace are in the config namespace (or usually the default one)
+                let namespace = wl.workload.namespace.clone().or_else(|| self.namespace.clone());
+                let resolved = Target {
+                    namespace: namespace.or_else(|| Some("default".into())),
+                    ..wl.workload.clone()
+                };
+                if workloads.contains(&resolved) {
+                    return invalid(format!("{target} is listed more than once"));
+                }
+                workloads.push(resolved);
+                if wl.timeout.or(self.timeout).is_some_and(|t| t < MIN_TIMEOUT) {
+                    let min = MIN_TIMEOUT.as_secs();
+                    return invalid(format!("{target} has a timeout below {min}s"));
+                }
+                if wl.container.as_ref().is_some_and(String::is_empty) {
+                    return invalid(format!("{target} has an empty container name"));
+                }
+                if wl.version.as_ref().is_some_and(String::is_empty) {
+                    return invalid(format!("{target} has an empty version"));
+                }
+                if wl
+                    .version_label
+                    .as_ref()
+                    .or(self.version_label.as_ref())
+                    .is_some_and(String::is_empty)
+                {
+                    return invalid(format!("{target} has an empty version label"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Groups in order, with defaults from the config applied to their workloads
+    ///
+    /// Groups are stages: each should only be tracked after the previous one succeeded.
+    pub fn stages(&self) -> Vec<Group> {
+        let defaults = |wl: &WorkloadConfig| WorkloadConfig {
+            timeout: wl.timeout.or(self.timeout),
+            rollback: wl.rollback.or(Some(self.rollback)),
+            version_label: wl.version_label.clone().or_else(|| self.version_label.clone()),
+            version_scheme: wl.version_scheme.or(self.version_scheme),
+            ..wl.clone()
+        };
+        let stages = self.groups.iter().map(|g| Group {
+            name: g.name.clone(),
+            workloads: g.workloads.iter().map(defaults).collect(),
+        });
+        stages.collect()
+    }
+}
+
+/// Parse and validate a yaml config
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let config: Config = serde_yaml::from_str(s).map_err(Error::Yaml)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// A workload in an optional cluster (kubeconfig context) and namespace
+///
+/// Syntax: `[context:][namespace/]kind/name`, e.g. `prod-eu:payments/deploy/api`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub context: Option<String>,
+    pub namespace: Option<String>,
+    pub kind: Kind,
+    pub name: String,
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        // contexts may contain colons (eks arns), but workloads never do
+        let (context, workload) = match value.rsplit_once(':') {
+            Some((ctx, wl)) => (Some(ctx.to_string()), wl),
+            None => (None, value),
+        };
+        let split = workload.splitn(3, '/').collect::<Vec<_>>();
+        let (namespace, kind, name) = match split.as_slice() {
+            [ns, kind, name] => (Some(ns.to_string()), kind, name),
+            [kind, name] => (None, kind, name),
+            _ => return Err(Error::InvalidWorkload(value.to_string())),
+        };
+        let empty = |s: &Option<String>| s.as_ref().is_some_and(String::is_empty);
+        if name.is_empty() || empty(&namespace) || empty(&context) {
+            return Err(Error::InvalidWorkload(value.to_string()));
+        }
+        Ok(Target {
+            context,
+            namespace,
+            kind: kind.parse()?,
+            name: name.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ctx) = &self.context {
+            write!(f, "{ctx}:")?;
+        }
+        if let Some(ns) = &self.namespace {
+            write!(f, "{ns}/")?;
+        }
+        write!(f, "{}/{}", self.kind, self.name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Deserialize a duration from seconds or a string like `90s`, `10m` or `1h30m`
+fn duration<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Option<Duration>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Secs(u64),
+        Text(String),
+    }
+    let parsed = match Option::<Raw>::deserialize(d)? {
+        None => return Ok(None),
+        Some(Raw::Secs(secs)) => Some(Duration::from_secs(secs)),
+        Some(Raw::Text(s)) => parse_duration(&s),
+    };
+    let invalid = || serde::de::Error::custom("invalid duration; expected seconds or e.g. 90s, 10m, 1h30m");
+    parsed.map(Some).ok_or_else(invalid)
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    if s.trim().is_empty() {
+        return None;
+    }
+    let (mut total, mut num) = (0u64, String::new());
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(num.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        num.clear();
+    }
+    if !num.is_empty() {
+        total = total.checked_add(num.parse::<u64>().ok()?)?; // trailing bare seconds
+    }
+    Some(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_config() {
+        let config: Config = r#"
+namespace: payments
+timeout: 10m
+version_scheme: calver
+groups:
+  - name: migrate
+    workloads:
+      - workload: job/migrate
+  - name: backend
+    workloads:
+      - workload: deploy/api
+        version: 1.4.2
+        container: api
+      - workload: prod-eu:payments/sts/ledger
+        timeout: 1h30m
+        rollback: undo
+        version_label: ledger.example.com/release
+        version_scheme: git
+"#
+        .parse()
+        .unwrap();
+        let stages = config.stages();
+        assert_eq!(stages[0].name, "migrate");
+        assert_eq!(stages[0].workloads[0].workload.kind, Kind::Job);
+        let wls = &stages[1].workloads;
+        assert_eq!(wls[0].workload.to_string(), "Deployment/api");
+        assert_eq!(wls[0].timeout, Some(Duration::from_secs(600)));
+        assert_eq!(wls[0].rollback, Some(RollbackPolicy::Never));
+        let ledger = &wls[1].workload;
+        assert_eq!(ledger.context.as_deref(), Some("prod-eu"));
+        assert_eq!(ledger.kind, Kind::StatefulSet);
+        assert_eq!(wls[1].timeout, Some(Duration::from_secs(5400)));
+        assert_eq!(wls[1].rollback, Some(RollbackPolicy::Undo));
+        assert!(matches!(wls[0].versioning().scheme, VersionScheme::Calver));
+        assert_eq!(wls[0].versioning().label, "app.kubernetes.io/version");
+        assert!(matches!(wls[1].versioning().scheme, VersionScheme::Created));
+        assert_eq!(wls[1].versioning().label, "ledger.example.com/release");
+    }
+
+    #[test]
+    fn parses_targets() {
+        let arn = "arn:aws:eks:eu-west-1:123456789012:cluster/prod";
+        let target: Target = format!("{arn}:payments/deploy/api").parse().unwrap();
+        assert_eq!(target.context.as_deref(), Some(arn));
+        assert_eq!(target.namespace.as_deref(), Some("payments"));
+        assert_eq!(target.kind, Kind::Deployment);
+        assert_eq!(target.name, "api");
+        assert_eq!(target.to_string(), format!("{arn}:payments/Deployment/api"));
+        let local: Target = "sts/db".parse().unwrap();
+        assert_eq!((local.context, local.namespace), (None, None));
+        assert!(":deploy/api".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let err = |yaml: &str| yaml.parse::<Config>().unwrap_err().to_string();
+        let unknown = err("groups: [{name: a, workloads: [{workload: cronjob/backup}]}]");
+        assert!(unknown.contains("Unknown workload kind: cronjob"), "{unknown}");
+        let dupe = err("groups: [{name: a, workloads: [{workload: deploy/a}, {workload: deploy/a}]}]");
+        assert_eq!(dupe, "Invalid config: Deployment/a is listed more than once");
+        let dupe = err("groups: [{name: a, workloads: [{workload: deploy/a}]}, {name: b, workloads: [{workload: default/deployment/a}]}]");
+        assert_eq!(
+            dupe,
+            "Invalid config: default/Deployment/a is listed more than once"
+        );
+        let dupe = err("namespace: shop\ngroups: [{name: a, workloads: [{workload: shop/deploy/a}, {workload: deploy/a}]}]");
+        assert_eq!(dupe, "Invalid config: Deployment/a is listed more than once");
+        let other = "namespace: shop\ngroups: [{name: a, workloads: [{workload: deploy/a}, {workload: default/deploy/a}]}]";
+        assert!(other.parse::<Config>().is_ok());
+        let notify =
+            "notify: [{url: http://localhost}]\ngroups: [{name: a, workloads: [{workload: deploy/a}]}]";
+        match notify.parse::<Config>() {
+            #[cfg(feature = "notify")]
+            Ok(config) => assert_eq!(config.notify.len(), 1),
+            #[cfg(not(feature = "notify"))]
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "Invalid config: notify requires the notify feature"
+            ),
+            other => panic!("unexpected {other:?}"),
+        }
+        let typo = err("groups: [{name: a, workloads: [{workload: deploy/a, timout: 5m}]}]");
+        assert!(typo.contains("unknown field `timout`"), "{typo}");
+        let timeout = err("groups: [{name: a, workloads: [{workload: deploy/a, timeout: 5 minutes}]}]");
+        assert!(timeout.contains("invalid duration"), "{timeout}");
+        for timeout in ["\"\"", "\"  \"", "99999999999999999h"] {
+            let yaml =
+                format!("groups: [{{name: a, workloads: [{{workload: deploy/a, timeout: {timeout}}}]}}]");
+            assert!(err(&yaml).contains("invalid duration"), "{timeout}");
+        }
+        let short = err("groups: [{name: a, workloads: [{workload: deploy/a, timeout: 5s}]}]");
+        assert_eq!(short, "Invalid config: Deployment/a has a timeout below 10s");
+        assert_eq!(err("groups: []"), "Invalid config: no groups of workloads");
+    }
+}
@@ -1,21 +1,194 @@
 //! debug rollout failures for potential reasons
 use crate::{
-    rollout::{PodSummary, ReplicaSetSummary},
-    Error, Kind, Result, Rollout, State,
+    rollout::{main_container, PodSummary, ReplicaSetSummary},
+    Kind, NodeDiagnosis, Result, Rollout, State,
 };
 
-use k8s_openapi::api::core::v1::Pod;
-use kube::core::ObjectList;
+use k8s_openapi::api::core::v1::{ContainerStatus, Pod};
+use kube::{core::ObjectList, ResourceExt};
+use serde::Serialize;
 #[allow(unused_imports)] use tracing::{debug, error, info, warn};
 
+/// Likely cause of a failing rollout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cause {
+    /// Containers crash on startup
+    CrashLoop,
+    /// Container images cannot be pulled
+    ImagePull,
+    /// Pods cannot be scheduled onto any node
+    Unschedulable,
+    /// Containers run but never become ready
+    NotReady,
+    /// Disruption budgets allow no disruptions of the pods
+    DisruptionBudget,
+    /// Nothing obviously wrong was found
+    Unknown,
+}
+
+impl std::fmt::Display for Cause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cause::CrashLoop => write!(f, "crash looping"),
+            Cause::ImagePull => write!(f, "image pull failure"),
+            Cause::Unschedulable => write!(f, "unschedulable"),
+            Cause::NotReady => write!(f, "not ready"),
+            Cause::DisruptionBudget => write!(f, "blocked by disruption budget"),
+            Cause::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Number of log lines fetched per container unless configured
+pub(crate) const DEFAULT_LOG_LINES: i64 = 30;
+
+/// Logs of a container that was not ready
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerLogs {
+    /// Name of the container
+    pub container: String,
+    /// Whether this is an init container (or native sidecar)
+    pub init: bool,
+    /// Whether the logs are from the previous instance of a restarted container
+    pub previous: bool,
+    /// Tail of the logs
+    pub logs: String,
+}
+
+impl std::fmt::Display for ContainerLogs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.init { "init container" } else { "container" };
+        write!(f, "{kind} {}", self.container)?;
+        if self.previous {
+            write!(f, " (previous instance)")?;
+        }
+        Ok(())
+    }
+}
+
+/// A pod inspected while debugging
+#[derive(Debug, Clone, Serialize)]
+pub struct PodDiagnosis {
+    /// Status summary of the pod
+    pub pod: PodSummary,
+    /// Tails of logs from containers that were not ready (main container first)
+    pub logs: Vec<ContainerLogs>,
+    /// The node a pod that was not ready is scheduled on
+    pub node: Option<NodeDiagnosis>,
+    /// Why the scheduler found no node for a pending pod
+    pub scheduling: Option<String>,
+}
+
+/// Findings from debugging a rollout
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnosis {
+    /// Most likely cause of the failure
+    pub cause: Cause,
+    /// The tracked replicaset (deployments only)
+    pub replicaset: Option<ReplicaSetSummary>,
+    /// Pods of the tracked revision
+    pub pods: Vec<PodDiagnosis>,
+    /// Disruption budgets that allow 0 disruptions of the pods
+    pub disruption_budgets: Vec<String>,
+}
+
+impl Diagnosis {
+    /// One line summary of the diagnosis
+    pub fn summary(&self) -> String {
+        let total = self.pods.len();
+        let ready = self.pods.iter().filter(|p| p.pod.is_ready()).count();
+        let mut summary = format!("{}: {ready}/{total} pods ready", self.cause);
+        if let Some(p) = self.pods.iter().find(|p| p.pod.reason.is_some()) {
+            summary += &format!(
+                " ({}: {})",
+                p.pod.name,
+                p.pod.reason.as_deref().unwrap_or_default()
+            );
+        }
+        if let Some(msg) = self.pods.iter().find_map(|p| p.scheduling.as_ref()) {
+            summary += &format!(" ({msg})");
+        }
+        if let Some(node) = self
+            .pods
+            .iter()
+            .find_map(|p| p.node.as_ref().filter(|n| n.has_problems()))
+        {
+            let problems = [node.conditions.clone(), node.untolerated_taints.clone()].concat();
+            summary += &format!(" (node {}: {})", node.name, problems.join(", "));
+        }
+        for pdb in &self.disruption_budgets {
+            summary += &format!(" (pdb {pdb} allows 0 disruptions)");
+        }
+        summary
+    }
+
+    /// Print the findings
+    pub fn print(&self) {
+        if let Some(rs) = &self.replicaset {
+            info!(
+                "{} Pod ReplicaSet {} running {}",
+                rs.replicas, rs.hash, rs.version
+            );
+            info!("Replicaset contains:");
+        }
+        for p in &self.pods {
+            println!("{:?}", p.pod);
+            if let Some(msg) = &p.scheduling {
+                println!("Scheduling failed: {msg}");
+            }
+            if let Some(node) = &p.node {
+                println!("Node {}:", node.name);
+                for c in &node.conditions {
+                    println!("  condition {c}");
+                }
+                for t in &node.untolerated_taints {
+                    println!("  untolerated taint {t}");
+                }
+                let resources = node.resources.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+                println!("  requested/allocatable: {}", resources.join(", "));
+            }
+            for l in &p.logs {
+                warn!("Last log lines from {l}:");
+                println!("{}", l.logs)
+            }
+        }
+        for pdb in &self.disruption_budgets {
+            warn!("PodDisruptionBudget {pdb} allows 0 disruptions (likely stalling the rollout)");
+        }
+        println!("{}", self.summary());
+    }
+}
+
 impl Rollout {
     /// Debug why a workload is in the state it is in
-    pub async fn debug(&self, state: &State) -> Result<()> {
-        match self.workload {
-            Kind::Deployment => debug_deployment(self, state).await,
-            Kind::StatefulSet => debug_statefulset(self, state).await,
-            Kind::DaemonSet => unimplemented!(),
-        }
+    ///
+    /// Prints and returns the findings from `Rollout::diagnose`.
+    pub async fn debug(&self, state: &State, log_lines: i64) -> Result<Diagnosis> {
+        let diag = self.diagnose(state, log_lines).await?;
+        diag.print();
+        Ok(diag)
+    }
+
+    /// Diagnose why a workload is in the state it is in
+    ///
+    /// Fetches up to `log_lines` log lines from every container that is not ready.
+    pub async fn diagnose(&self, state: &State, log_lines: i64) -> Result<Diagnosis> {
+        let (replicaset, pods) = match self.workload {
+            Kind::Deployment => diagnose_deployment(self, state, log_lines).await?,
+            Kind::StatefulSet | Kind::DaemonSet | Kind::Job => {
+                // For now, just list the pods as if there were no replicaset to worry about
+                let pods = self.get_pods(&state.selector).await?;
+                (None, diagnose_pods(self, pods, log_lines).await?)
+            }
+        };
+        let disruption_budgets = diagnose_disruption_budgets(self, state).await;
+        Ok(Diagnosis {
+            cause: classify(&pods, &disruption_budgets),
+            replicaset,
+            pods,
+            disruption_budgets,
+        })
     }
 }
 
@@ -24,51 +197,188 @@ impl Rollout {
 /// Finds active replicaset (with pods in them)
 /// Debugs the pods in each replicaset
 /// Tails the logs from each broken pod
-async fn debug_deployment(r: &Rollout, state: &State) -> Result<()> {
+async fn diagnose_deployment(
+    r: &Rollout,
+    state: &State,
+    log_lines: i64,
+) -> Result<(Option<ReplicaSetSummary>, Vec<PodDiagnosis>)> {
     // NB: this can technically loop over all replicasets with non-zero replicas
     // but the output would be confusing, better to stick with the one we tracked
     let Some(rs) = r.get_rs(&state.selector).await? else {
-        return Ok(());
+        return Ok((None, vec![]));
     };
-    let summary = ReplicaSetSummary::try_from(rs)?;
+    let summary = ReplicaSetSummary::new(rs, r.container.as_deref())?;
     if summary.replicas == 0 {
-        return Ok(());
+        return Ok((Some(summary), vec![]));
     }
-    info!(
-        "{} Pod ReplicaSet {} running {}",
-        summary.replicas, summary.hash, summary.version
-    );
-    let pods = r.get_pods(&state.selector).await?;
-    info!("Replicaset contains:");
-    debug_pods(r, pods).await?;
-    Ok(())
-}
-
-async fn debug_statefulset(r: &Rollout, state: &State) -> Result<()> {
-    // For now, just list the pods as if there were no replicaset to worry about
     let pods = r.get_pods(&state.selector).await?;
-    //debug!("Statefulset contains: {pods:?}");
-    debug_pods(r, pods).await?;
-    Ok(())
+    Ok((Some(summary), diagnose_pods(r, pods, log_lines).await?))
 }
 
-async fn debug_pods(r: &Rollout, pods: ObjectList<Pod>) -> Result<()> {
+async fn diagnose_pods(r: &Rollout, pods: ObjectList<Pod>, log_lines: i64) -> Result<Vec<PodDiagnosis>> {
+    let mut res = vec![];
     for pod in pods {
-        let podstate = PodSummary::try_from(pod)?;
-        println!("{:?}", podstate);
-        if podstate.running != podstate.containers as i32 {
-            info!(
-                "Fetching logs from non-ready main container in pod: {}",
-                podstate.name
-            );
-            match r.get_pod_logs(&podstate.name).await {
-                Ok(logs) => {
-                    warn!("Last 30 log lines:");
-                    println!("{}", logs)
+        let failing = failing_containers(&pod, r.container.as_deref());
+        let (node, scheduling) = diagnose_placement(r, &pod).await;
+        let podstate = PodSummary::new(pod, r.container.as_deref())?;
+        let mut logs = vec![];
+        for (status, init) in failing {
+            // a restarted container's crash output lives in its previous instance
+            let waiting = status.state.as_ref().is_some_and(|s| s.waiting.is_some());
+            let instances = [(false, !waiting), (true, status.restart_count > 0)];
+            for previous in instances
+                .into_iter()
+                .filter_map(|(prev, fetch)| fetch.then_some(prev))
+            {
+                debug!("Fetching logs from {} in pod: {}", status.name, podstate.name);
+                match r
+                    .get_pod_logs(&podstate.name, &status.name, previous, log_lines)
+                    .await
+                {
+                    Ok(l) => logs.push(ContainerLogs {
+                        container: status.name.clone(),
+                        init,
+                        previous,
+                        logs: l,
+                    }),
+                    Err(e) => warn!(
+                        "Failed to get logs from {} in {}: {}",
+                        status.name, podstate.name, e
+                    ),
                 }
-                Err(e) => warn!("Failed to get logs from {}: {}", podstate.name, e),
             }
         }
+        res.push(PodDiagnosis {
+            pod: podstate,
+            logs,
+            node,
+            scheduling,
+        });
+    }
+    Ok(res)
+}
+
+/// Node context for a pod that is not ready, or why a pending pod was not scheduled
+async fn diagnose_placement(r: &Rollout, pod: &Pod) -> (Option<NodeDiagnosis>, Option<String>) {
+    let ready = pod
+        .status
+        .iter()
+        .flat_map(|s| s.conditions.iter().flatten())
+        .any(|c| c.type_ == "Ready" && c.status == "True");
+    if ready {
+        return (None, None);
+    }
+    let scheduled = pod.spec.as_ref().is_some_and(|s| s.node_name.is_some());
+    if !scheduled {
+        return match r.get_scheduling_failure(&pod.name_any()).await {
+            Ok(msg) => (None, msg),
+            Err(e) => {
+                warn!("Unable to get scheduling events for {}: {e}", pod.name_any());
+                (None, None)
+            }
+        };
+    }
+    match r.diagnose_node(pod).await {
+        Ok(node) => (node, None),
+        Err(e) => {
+            warn!("Unable to inspect node of {}: {e}", pod.name_any());
+            (None, None)
+        }
+    }
+}
+
+/// Statuses of containers that are not ready and have run at some point
+///
+/// Init containers (including native sidecars) are flagged, and the main container comes first.
+fn failing_containers(pod: &Pod, container: Option<&str>) -> Vec<(ContainerStatus, bool)> {
+    let Some(status) = &pod.status else {
+        return vec![];
+    };
+    let main = main_container(pod, container).map(|c| c.name.as_str());
+    let mut containers = status.container_statuses.clone().unwrap_or_default();
+    containers.sort_by_key(|s| Some(s.name.as_str()) != main);
+    let inits = status.init_container_statuses.clone().unwrap_or_default();
+    let all = containers.into_iter().map(|s| (s, false));
+    all.chain(inits.into_iter().map(|s| (s, true)))
+        .filter(|(s, _)| {
+            let state = s.state.clone().unwrap_or_default();
+            let completed = state.terminated.is_some_and(|t| t.exit_code == 0);
+            let never_ran = state.waiting.is_some() && s.restart_count == 0;
+            !s.ready && !completed && !never_ran
+        })
+        .collect()
+}
+
+/// Find disruption budgets that currently allow no disruptions of the tracked pods
+async fn diagnose_disruption_budgets(r: &Rollout, state: &State) -> Vec<String> {
+    match r.get_blocking_disruption_budgets(&state.selector).await {
+        Ok(pdbs) => pdbs.iter().map(|pdb| pdb.name_any()).collect(),
+        Err(e) => {
+            warn!("Unable to check disruption budgets: {e}");
+            vec![]
+        }
+    }
+}
+
+fn classify(pods: &[PodDiagnosis], disruption_budgets: &[String]) -> Cause {
+    let has_reason = |reasons: &[&str]| {
+        pods.iter()
+            .filter_map(|p| p.pod.reason.as_deref())
+            .any(|r| reasons.contains(&r))
+    };
+    if has_reason(&["ErrImagePull", "ImagePullBackOff", "InvalidImageName"]) {
+        Cause::ImagePull
+    } else if has_reason(&["CrashLoopBackOff", "RunContainerError", "CreateContainerError"]) {
+        Cause::CrashLoop
+    } else if has_reason(&["Unschedulable"]) {
+        Cause::Unschedulable
+    } else if pods.iter().any(|p| !p.pod.is_ready()) {
+        Cause::NotReady
+    } else if !disruption_budgets.is_empty() {
+        Cause::DisruptionBudget
+    } else {
+        Cause::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    #[test]
+    fn failing_containers_main_first() {
+        let pod = testing::pod(json!({
+            "metadata": {
+                "annotations": { "kubectl.kubernetes.io/default-container": "app" }
+            },
+            "spec": { "containers": [{ "name": "proxy" }, { "name": "app" }] },
+            "status": {
+                "initContainerStatuses": [
+                    { "name": "migrate", "ready": false, "restartCount": 0, "image": "m", "imageID": "",
+                      "state": { "terminated": { "exitCode": 0 } } },
+                    { "name": "vault", "ready": false, "restartCount": 2, "image": "v", "imageID": "",
+                      "state": { "waiting": { "reason": "CrashLoopBackOff" } } }
+                ],
+                "containerStatuses": [
+                    { "name": "proxy", "ready": false, "restartCount": 0, "image": "p", "imageID": "",
+                      "state": { "running": {} } },
+                    { "name": "app", "ready": false, "restartCount": 3, "image": "a", "imageID": "",
+                      "state": { "running": {} } },
+                    { "name": "pending", "ready": false, "restartCount": 0, "image": "x", "imageID": "",
+                      "state": { "waiting": { "reason": "ContainerCreating" } } }
+                ]
+            }
+        }));
+        let failing = failing_containers(&pod, None)
+            .into_iter()
+            .map(|(s, init)| (s.name, init))
+            .collect::<Vec<_>>();
+        assert_eq!(failing, [
+            ("app".to_string(), false),
+            ("proxy".to_string(), false),
+            ("vault".to_string(), true)
+        ]);
     }
-    Ok(())
 }
@@ -0,0 +1,221 @@
+//! revision history of a workload and rolling back through it
+use crate::{
+    audit::{DURATION_ANNOTATION, OUTCOME_ANNOTATION},
+    rollout::{age, format_duration, seconds, template_version, REVISION_ANNOTATION},
+    Error, Kind, Result, Rollout,
+};
+
+use k8s_openapi::api::{
+    apps::v1::{ControllerRevision, DaemonSet, Deployment, ReplicaSet, StatefulSet},
+    core::v1::PodTemplateSpec,
+};
+use kube::{
+    api::{ListParams, PostParams},
+    core::Selector,
+    Resource, ResourceExt,
+};
+use serde::Serialize;
+use time::Duration;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Annotation kubectl records the reason for a change in
+const CHANGE_CAUSE_ANNOTATION: &str = "kubernetes.io/change-cause";
+
+/// A retained revision of a workload
+#[derive(Debug, Clone, Serialize)]
+pub struct Revision {
+    /// Revision number
+    pub revision: i64,
+    /// Hash identifying the revision (replicaset template hash or controllerrevision name)
+    pub hash: String,
+    /// Version of the main container
+    pub version: Option<String>,
+    /// Reason recorded for the change
+    pub change_cause: Option<String>,
+    /// Time since the revision was created
+    #[serde(serialize_with = "seconds")]
+    pub age: Duration,
+    /// Pods currently running this revision
+    pub replicas: i32,
+    /// How irae's tracking of the revision concluded
+    pub outcome: Option<String>,
+    /// Seconds irae's tracking of the revision took
+    pub duration: Option<u64>,
+}
+
+impl Revision {
+    /// Age in a coarse human readable form
+    pub fn age(&self) -> String {
+        format_duration(self.age)
+    }
+
+    fn new(revision: i64, hash: String, meta: &kube::core::ObjectMeta) -> Self {
+        let annotations = meta.annotations.clone().unwrap_or_default();
+        Revision {
+            revision,
+            hash,
+            version: None,
+            change_cause: annotations.get(CHANGE_CAUSE_ANNOTATION).cloned(),
+            age: age(meta.creation_timestamp.as_ref()),
+            replicas: 0,
+            outcome: annotations.get(OUTCOME_ANNOTATION).cloned(),
+            duration: annotations.get(DURATION_ANNOTATION).and_then(|d| d.parse().ok()),
+        }
+    }
+}
+
+impl Rollout {
+    /// List the retained revisions of the workload, oldest first
+    pub async fn history(&self) -> Result<Vec<Revision>> {
+        let revisions = self.revisions().await?;
+        Ok(revisions.into_iter().map(|(rev, _)| rev).collect())
+    }
+
+    /// Roll the workload back to the revision before its latest, like `kubectl rollout undo`
+    ///
+    /// Returns the revision rolled back to, or `None` when no earlier revision is retained.
+    pub async fn undo(&self) -> Result<Option<Revision>> {
+        let mut revisions = self.revisions().await?;
+        revisions.pop(); // latest
+        let Some((previous, Some(template))) = revisions.pop() else {
+            return Ok(None);
+        };
+        let pp = PostParams::default();
+        match self.workload {
+            Kind::Deployment => {
+                let mut d = self.get_deploy().await?;
+                let mut template = template;
+                // the hash label is added by the deployment controller for each replicaset
+                if let Some(labels) = template.metadata.as_mut().and_then(|m| m.labels.as_mut()) {
+                    labels.remove("pod-template-hash");
+                }
+                d.spec.get_or_insert_default().template = template;
+                self.ns::<Deployment>()
+                    .replace(&self.name, &pp, &d)
+                    .await
+                    .map_err(Error::Kube)?;
+            }
+            Kind::StatefulSet => {
+                let mut sts = self.get_statefulset().await?;
+                sts.spec.get_or_insert_default().template = template;
+                self.ns::<StatefulSet>()
+                    .replace(&self.name, &pp, &sts)
+                    .await
+                    .map_err(Error::Kube)?;
+            }
+            Kind::DaemonSet => {
+                let mut ds = self.get_daemonset().await?;
+                ds.spec.get_or_insert_default().template = template;
+                self.ns::<DaemonSet>()
+                    .replace(&self.name, &pp, &ds)
+                    .await
+                    .map_err(Error::Kube)?;
+            }
+            Kind::Job => return Ok(None), // jobs have no revisions
+        }
+        info!("{} rolled back to revision {}", self.name, previous.revision);
+        Ok(Some(previous))
+    }
+
+    /// Retained revisions with their pod templates, oldest first
+    async fn revisions(&self) -> Result<Vec<(Revision, Option<PodTemplateSpec>)>> {
+        let params = self.infer_parameters().await?;
+        let selector = Selector::try_from(params.selector)
+            .map_err(|e| Error::KubeInvariant(format!("malformed label selector: {e}")))?;
+        let lp = ListParams::default().labels_from(&selector);
+        let container = self.container.as_deref();
+        let mut revisions = match self.workload {
+            Kind::Deployment => {
+                let sets = self.ns::<ReplicaSet>().list(&lp).await.map_err(Error::Kube)?;
+                sets.into_iter()
+                    .filter(|rs| owned_by(rs.meta(), &self.name))
+                    .map(|rs| {
+                        let number = rs
+                            .annotations()
+                            .get(REVISION_ANNOTATION)
+                            .and_then(|r| r.parse().ok());
+                        let hash = rs.labels().get("pod-template-hash").cloned();
+                        let mut rev =
+                            Revision::new(number.unwrap_or_default(), hash.unwrap_or_default(), rs.meta());
+                        let template = rs.spec.and_then(|s| s.template);
+                        rev.version = template.as_ref().and_then(|tpl| template_version(tpl, container));
+                        rev.replicas = rs.status.map(|s| s.replicas).unwrap_or_default();
+                        (rev, template)
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Kind::StatefulSet | Kind::DaemonSet => {
+                let crs = self
+                    .ns::<ControllerRevision>()
+                    .list(&lp)
+                    .await
+                    .map_err(Error::Kube)?;
+                let pods = self.get_pods(&selector).await?;
+                crs.into_iter()
+                    .filter(|cr| owned_by(cr.meta(), &self.name))
+                    .map(|cr| {
+                        let mut rev = Revision::new(cr.revision, cr.name_any(), cr.meta());
+                        let template = controller_revision_template(&cr);
+                        rev.version = template.as_ref().and_then(|tpl| template_version(tpl, container));
+                        // daemonset pods are labelled with the hash without the name prefix
+                        let suffix = cr
+                            .name_any()
+                            .trim_start_matches(&format!("{}-", self.name))
+                            .to_string();
+                        let count = pods.iter().filter(|p| {
+                            let label = p.labels().get("controller-revision-hash");
+                            label == Some(&cr.name_any()) || label == Some(&suffix)
+                        });
+                        rev.replicas = count.count() as i32;
+                        (rev, template)
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Kind::Job => vec![], // jobs are immutable and have no revisions
+        };
+        revisions.sort_by_key(|(r, _)| r.revision);
+        Ok(revisions)
+    }
+}
+
+fn owned_by(meta: &kube::core::ObjectMeta, name: &str) -> bool {
+    let mut owners = meta.owner_references.iter().flatten();
+    owners.any(|o| o.controller == Some(true) && o.name == name)
+}
+
+/// The pod template stored in a controllerrevision patch
+fn controller_revision_template(cr: &ControllerRevision) -> Option<PodTemplateSpec> {
+    let template = cr.data.as_ref()?.0.get("spec")?.get("template")?;
+    serde_json::from_value(template.clone()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    #[test]
+    fn reads_controller_revisions() {
+        let cr: ControllerRevision = testing::object(json!({
+            "metadata": {
+                "name": "web-7d9f8c",
+                "ownerReferences": [{
+                    "apiVersion": "apps/v1", "kind": "StatefulSet", "name": "web", "uid": "1", "controller": true
+                }],
+                "annotations": { "irae/outcome": "timed out", "irae/duration": "600" }
+            },
+            "revision": 3,
+            "data": { "spec": { "template": {
+                "spec": { "containers": [{ "name": "web", "image": "web:1.4.2" }] }
+            }}}
+        }));
+        assert!(owned_by(cr.meta(), "web"));
+        assert!(!owned_by(cr.meta(), "db"));
+        let template = controller_revision_template(&cr).unwrap();
+        assert_eq!(template_version(&template, None).as_deref(), Some("1.4.2"));
+        let rev = Revision::new(cr.revision, cr.name_any(), cr.meta());
+        assert_eq!(rev.outcome.as_deref(), Some("timed out"));
+        assert_eq!(rev.duration, Some(600));
+    }
+}
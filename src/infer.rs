@@ -44,6 +44,19 @@ impl Rollout {
                     initial_delay_seconds: find_sts_delay(&sts),
                 }
             }
+            Kind::Job => {
+                let job = self.get_job().await?;
+                let spec = job.spec.unwrap_or_default();
+                Inference {
+                    selector: spec
+                        .selector
+                        .ok_or_else(|| Error::KubeInvariant("no selector on job".to_string()))?,
+                    // jobs without completions succeed with the first succeeding pod
+                    min_replicas: spec.completions.unwrap_or(1).try_into().unwrap_or(1),
+                    strategy: None,
+                    initial_delay_seconds: None,
+                }
+            }
             Kind::DaemonSet => {
                 let ds = self.get_daemonset().await?;
                 Inference {
@@ -109,17 +122,17 @@ fn find_ds_replicas(d: &DaemonSet) -> Option<u32> {
 fn find_deploy_delay(d: &Deployment) -> Option<u32> {
     let spec = d.spec.as_ref()?;
     let tpl = spec.template.spec.as_ref()?;
-    find_pod_delay(&tpl)
+    find_pod_delay(tpl)
 }
 fn find_sts_delay(d: &StatefulSet) -> Option<u32> {
     let spec = d.spec.as_ref()?;
     let tpl = spec.template.spec.as_ref()?;
-    find_pod_delay(&tpl)
+    find_pod_delay(tpl)
 }
 fn find_ds_delay(d: &DaemonSet) -> Option<u32> {
     let spec = d.spec.as_ref()?;
     let tpl = spec.template.spec.as_ref()?;
-    find_pod_delay(&tpl)
+    find_pod_delay(tpl)
 }
 fn find_pod_delay(p: &PodSpec) -> Option<u32> {
     let mut max_delay = 0;
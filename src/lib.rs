@@ -1,5 +1,6 @@
 use kube::{Resource, ResourceExt};
 use semver::Version;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -13,12 +14,44 @@ pub enum Error {
     #[error("Time Error: {0}")]
     Time(#[source] time::Error),
 
+    #[error("IoError: {0}")]
+    Io(#[source] std::io::Error),
+
+    #[cfg(feature = "metrics")]
+    #[error("MetricsError: {0}")]
+    Metrics(#[source] prometheus::Error),
+
+    #[cfg(feature = "notify")]
+    #[error("NotifyError: {0}")]
+    Notify(#[source] reqwest::Error),
+
     #[error("IllegalDocument")]
     IllegalDocument,
 
-    #[error("Non-semver app.kubernetes.io/version: {0}")]
+    #[error("Unknown workload kind: {0}")]
+    UnknownKind(String),
+
+    #[error("Invalid workload {0}; syntax: [context:][namespace/]kind/name")]
+    InvalidWorkload(String),
+
+    #[error("Workload not found: {0}")]
+    WorkloadNotFound(String),
+
+    #[error("Invalid object reference: {0}")]
+    InvalidReference(String),
+
+    #[error("YamlError: {0}")]
+    Yaml(#[source] serde_yaml::Error),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Non-semver {0}")]
     NonSemverVersion(String),
 
+    #[error("Invalid version: {0}")]
+    InvalidVersion(String),
+
     #[error("K8s Invariant Error: {0}")]
     KubeInvariant(String),
 }
@@ -31,26 +64,75 @@ macro_rules! bail {
 }
 
 impl Error {
+    /// Bounded label for the error kind (without its payload)
     pub fn metric_label(&self) -> String {
-        format!("{self:?}").to_lowercase()
+        let label = match self {
+            Error::Serialization(_) => "serialization",
+            Error::Kube(_) => "kube",
+            Error::Time(_) => "time",
+            Error::Io(_) => "io",
+            #[cfg(feature = "metrics")]
+            Error::Metrics(_) => "metrics",
+            #[cfg(feature = "notify")]
+            Error::Notify(_) => "notify",
+            Error::IllegalDocument => "illegaldocument",
+            Error::UnknownKind(_) => "unknownkind",
+            Error::InvalidWorkload(_) => "invalidworkload",
+            Error::WorkloadNotFound(_) => "workloadnotfound",
+            Error::InvalidReference(_) => "invalidreference",
+            Error::Yaml(_) => "yaml",
+            Error::InvalidConfig(_) => "invalidconfig",
+            Error::NonSemverVersion(_) => "nonsemverversion",
+            Error::InvalidVersion(_) => "invalidversion",
+            Error::KubeInvariant(_) => "kubeinvariant",
+        };
+        label.to_string()
     }
 }
 
+pub mod audit;
+mod builder;
+pub use builder::RolloutBuilder;
+pub mod config;
 mod debug;
+pub use debug::{Cause, ContainerLogs, Diagnosis, PodDiagnosis};
 mod rollout;
-pub use rollout::{DeploySummary, State, StatefulSummary};
+pub use rollout::{DeploySummary, Outcome, PodSummary, ReplicaSetSummary, State, StatefulSummary, Verdict};
+mod track;
+pub use track::Observer;
 pub mod estimate;
+pub mod watch;
 pub use estimate::RolloutStrategy;
 mod infer;
 pub use infer::Inference;
+mod node;
+pub use node::{NodeDiagnosis, ResourceUsage};
+mod policy;
+mod preflight;
+pub use preflight::Preflight;
+mod status;
+pub use status::Snapshot;
+mod history;
+mod verify;
+mod version;
+pub use history::Revision;
+pub use version::{VersionScheme, Versioning};
+#[cfg(feature = "metrics")] pub mod metrics;
+#[cfg(feature = "notify")] pub mod notify;
+#[cfg(feature = "replay")] pub mod replay;
+#[cfg(feature = "server")] pub mod server;
 #[cfg(feature = "term")] pub mod term;
+#[cfg(test)] mod testing;
 
+#[deprecated(note = "use `Versioning`, which supports other labels and version schemes")]
 pub fn version_label<K: Resource>(k: &K) -> Result<Version> {
-    if let Some(v) = k.labels().get("app.kubernetes.io/version") {
-        let sem = Version::parse(v.as_ref()).map_err(|e| Error::NonSemverVersion(format!("{v}: {e}")))?;
+    let label = version::VERSION_LABEL.to_string();
+    if let Some(v) = k.labels().get(&label) {
+        let sem =
+            Version::parse(v.as_ref()).map_err(|e| Error::NonSemverVersion(format!("{label}: {v}: {e}")))?;
         Ok(sem)
     } else {
-        Err(Error::NonSemverVersion(format!("missing label")))
+        Err(Error::NonSemverVersion(format!("{label}: missing label")))
     }
 }
 
@@ -63,22 +145,127 @@ pub struct Rollout {
     pub namespace: Option<String>,
     /// The type of workload it is
     pub workload: Kind,
+    /// What to do when a newer revision replaces the one being tracked
+    pub on_supersede: SupersedePolicy,
+    /// Maximum time to wait for the rollout (estimated from the workload if unset)
+    pub timeout: Option<std::time::Duration>,
+    /// Name of the main container (defaults to the kubectl default-container annotation or the first)
+    pub container: Option<String>,
+    /// Version (image tag) or image digest (`sha256:..`) the ready pods must run
+    pub expect_version: Option<String>,
+    /// Label with the version of a revision, and how versions are ordered to find the newest
+    pub versioning: Versioning,
+    /// How often to poll the status while tracking
+    pub poll: PollStrategy,
     /// Kubernetes interface
     pub client: kube::Client,
+    /// Name of the cluster (kubeconfig context) the client talks to, for labelling output
+    pub cluster: Option<String>,
+}
+
+impl Rollout {
+    /// The namespace of the workload, falling back to the context namespace
+    pub fn namespace_or_default(&self) -> String {
+        self.namespace
+            .clone()
+            .unwrap_or_else(|| self.client.default_namespace().to_string())
+    }
+
+    /// The workload as `namespace/Kind/name`, prefixed by `cluster:` when labelled
+    pub fn workload_ref(&self) -> String {
+        let wl = format!("{}/{}/{}", self.namespace_or_default(), self.workload, self.name);
+        match &self.cluster {
+            Some(c) => format!("{c}:{wl}"),
+            None => wl,
+        }
+    }
+}
+
+/// Policy for when the tracked revision is replaced by a newer apply during tracking
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "term", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum SupersedePolicy {
+    /// Switch to tracking the newer revision
+    #[default]
+    Follow,
+    /// Stop tracking with a `Verdict::Superseded`
+    Abort,
+}
+
+/// How often the status of a rollout is polled while tracking
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PollStrategy {
+    /// 19 polls spread evenly over the timeout
+    #[default]
+    Spread,
+    /// Poll at a fixed interval (of at least 100ms) until the timeout
+    Every(std::time::Duration),
+    /// Poll the given number of times without waiting (for replaying recorded sessions)
+    #[cfg(feature = "replay")]
+    Immediately(u64),
+}
+
+/// Policy for when a rollout does not complete in time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "term", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum RollbackPolicy {
+    /// Leave the workload as it is
+    #[default]
+    Never,
+    /// Roll back to the previous revision with `Rollout::undo`
+    Undo,
 }
 /// Support kinds to track rollouts for
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 pub enum Kind {
     Deployment,
     StatefulSet,
     DaemonSet,
+    /// A job that runs to completion (e.g. a migration before a rollout)
+    Job,
     //Kustomization
 }
 
+/// Parse kinds from their kubectl names or short names
+impl std::str::FromStr for Kind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_ref() {
+            "deploy" | "deployment" | "deployments" => Ok(Kind::Deployment),
+            "sts" | "statefulset" | "statefulsets" => Ok(Kind::StatefulSet),
+            "ds" | "daemonset" | "daemonsets" => Ok(Kind::DaemonSet),
+            "job" | "jobs" => Ok(Kind::Job),
+            _ => Err(Error::UnknownKind(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Deployment => write!(f, "Deployment"),
+            Kind::StatefulSet => write!(f, "StatefulSet"),
+            Kind::DaemonSet => write!(f, "DaemonSet"),
+            Kind::Job => write!(f, "Job"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn kind_matches_api_kind() {
+        assert_eq!(Kind::Deployment.to_string(), "Deployment");
+        assert_eq!(Kind::StatefulSet.to_string(), "StatefulSet");
+        assert_eq!(Kind::DaemonSet.to_string(), "DaemonSet");
+        assert_eq!(Kind::Job.to_string(), "Job");
+    }
 }
@@ -0,0 +1,154 @@
+//! prometheus metrics for rollout outcomes
+use crate::{Error, Result, Rollout, Verdict};
+
+use axum::{http::StatusCode, response::IntoResponse, routing::get, Router};
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+use std::{path::Path, time::Duration};
+use tokio::net::TcpListener;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Rollout metrics in a prometheus registry
+///
+/// Cheap to clone; all clones share the same registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    started: IntCounterVec,
+    succeeded: IntCounterVec,
+    failed: IntCounterVec,
+    timed_out: IntCounterVec,
+    abandoned: IntCounterVec,
+    duration: HistogramVec,
+    errors: IntCounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let labels = &["namespace", "kind"];
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            IntCounterVec::new(Opts::new(name, help), labels).expect("valid counter")
+        };
+        let metrics = Metrics {
+            registry: Registry::new(),
+            started: counter("irae_rollouts_started_total", "Rollouts started", labels),
+            succeeded: counter("irae_rollouts_succeeded_total", "Rollouts succeeded", labels),
+            failed: counter("irae_rollouts_failed_total", "Rollouts failed", labels),
+            timed_out: counter("irae_rollouts_timed_out_total", "Rollouts timed out", labels),
+            abandoned: counter(
+                "irae_rollouts_abandoned_total",
+                "Rollouts paused or superseded",
+                labels,
+            ),
+            duration: HistogramVec::new(
+                HistogramOpts::new("irae_rollout_duration_seconds", "Duration of tracked rollouts")
+                    .buckets(vec![10., 30., 60., 120., 300., 600., 1200., 1800., 3600.]),
+                labels,
+            )
+            .expect("valid histogram"),
+            errors: counter("irae_errors_total", "Errors while tracking", &["error"]),
+        };
+        let collectors = [
+            &metrics.started,
+            &metrics.succeeded,
+            &metrics.failed,
+            &metrics.timed_out,
+            &metrics.abandoned,
+            &metrics.errors,
+        ];
+        for c in collectors {
+            metrics
+                .registry
+                .register(Box::new(c.clone()))
+                .expect("unique metric");
+        }
+        metrics
+            .registry
+            .register(Box::new(metrics.duration.clone()))
+            .expect("unique metric");
+        metrics
+    }
+}
+
+impl Metrics {
+    /// Record that tracking of a rollout has started
+    pub fn started(&self, r: &Rollout) {
+        self.started.with_label_values(&labels(r)).inc();
+    }
+
+    /// Record the verdict and duration of a tracked rollout
+    pub fn finished(&self, r: &Rollout, verdict: &Verdict, duration: Duration) {
+        let labels = labels(r);
+        let counter = match verdict {
+            Verdict::Succeeded | Verdict::Unchanged => &self.succeeded,
+            Verdict::TimedOut => &self.timed_out,
+            Verdict::WrongVersion(_) | Verdict::Failed(_) => &self.failed,
+            Verdict::Superseded(_) | Verdict::Paused => &self.abandoned,
+        };
+        counter.with_label_values(&labels).inc();
+        self.duration
+            .with_label_values(&labels)
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record a rollout that failed to track due to an error
+    pub fn failed(&self, r: &Rollout, err: &Error) {
+        self.failed.with_label_values(&labels(r)).inc();
+        self.error(err);
+    }
+
+    /// Record an error
+    pub fn error(&self, err: &Error) {
+        self.errors.with_label_values(&[err.metric_label()]).inc();
+    }
+
+    /// Render the registry in the prometheus text format
+    pub fn render(&self) -> Result<String> {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .map_err(Error::Metrics)
+    }
+
+    /// Write the registry to a textfile for the node-exporter textfile collector
+    ///
+    /// Written via a temporary file and renamed so the collector never sees a partial file.
+    pub fn write_textfile(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("prom.tmp");
+        std::fs::write(&tmp, self.render()?).map_err(Error::Io)?;
+        std::fs::rename(&tmp, path).map_err(Error::Io)
+    }
+
+    /// Serve the registry on `/metrics` until the process exits
+    ///
+    /// Takes a bound listener so callers see bind errors before serving in the background.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        let app = Router::new().route("/metrics", get(move || async move { self.response() }));
+        if let Ok(addr) = listener.local_addr() {
+            info!("Serving metrics on {addr}");
+        }
+        axum::serve(listener, app).await.map_err(Error::Io)
+    }
+
+    fn response(&self) -> impl IntoResponse {
+        match self.render() {
+            Ok(body) => (StatusCode::OK, body),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+}
+
+fn labels(r: &Rollout) -> [String; 2] {
+    [r.namespace_or_default(), r.workload.to_string()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_error_counts() {
+        let m = Metrics::default();
+        m.error(&Error::KubeInvariant("something unique".into()));
+        let out = m.render().unwrap();
+        assert!(out.contains(r#"irae_errors_total{error="kubeinvariant"} 1"#));
+    }
+}
@@ -0,0 +1,240 @@
+//! node level context for pods that fail to become ready
+use crate::{Error, Result, Rollout};
+
+use k8s_openapi::{
+    api::core::v1::{Event, Node, Pod, Taint, Toleration},
+    apimachinery::pkg::api::resource::Quantity,
+};
+use kube::{api::ListParams, Api};
+use serde::Serialize;
+use std::collections::BTreeMap;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Findings about the node a pod is scheduled on
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeDiagnosis {
+    /// Name of the node
+    pub name: String,
+    /// Conditions signalling a problem (not Ready, or under pressure)
+    pub conditions: Vec<String>,
+    /// Taints on the node that the pod does not tolerate
+    pub untolerated_taints: Vec<String>,
+    /// Allocatable versus requested resources on the node
+    pub resources: Vec<ResourceUsage>,
+}
+
+impl NodeDiagnosis {
+    /// Whether the node itself looks unhealthy for the pod
+    pub fn has_problems(&self) -> bool {
+        !self.conditions.is_empty() || !self.untolerated_taints.is_empty()
+    }
+}
+
+/// Requests of all pods on a node against what the node can allocate
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceUsage {
+    /// Resource name (cpu, memory or pods)
+    pub resource: String,
+    /// Allocatable amount (cores, bytes or count)
+    pub allocatable: f64,
+    /// Requested amount (cores, bytes or count)
+    pub requested: f64,
+}
+
+impl std::fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (req, alloc) = (self.requested, self.allocatable);
+        match self.resource.as_str() {
+            "cpu" => write!(f, "cpu {req:.2}/{alloc:.2}"),
+            "memory" => write!(f, "memory {:.0}Mi/{:.0}Mi", req / 1048576.0, alloc / 1048576.0),
+            r => write!(f, "{r} {req}/{alloc}"),
+        }
+    }
+}
+
+impl Rollout {
+    /// Inspect the node a pod is scheduled on
+    pub async fn diagnose_node(&self, pod: &Pod) -> Result<Option<NodeDiagnosis>> {
+        let Some(name) = pod.spec.as_ref().and_then(|s| s.node_name.clone()) else {
+            return Ok(None);
+        };
+        let node = Api::<Node>::all(self.client.clone())
+            .get(&name)
+            .await
+            .map_err(Error::Kube)?;
+        let lp = ListParams::default().fields(&format!("spec.nodeName={name}"));
+        let pods = Api::<Pod>::all(self.client.clone())
+            .list(&lp)
+            .await
+            .map_err(Error::Kube)?;
+        let tolerations = pod.spec.as_ref().and_then(|s| s.tolerations.clone());
+        let taints = node.spec.as_ref().and_then(|s| s.taints.clone());
+        Ok(Some(NodeDiagnosis {
+            name,
+            conditions: node_problems(&node),
+            untolerated_taints: untolerated(&taints.unwrap_or_default(), &tolerations.unwrap_or_default()),
+            resources: resource_usage(&node, &pods.items),
+        }))
+    }
+
+    /// The latest reason the scheduler gave for not placing a pod
+    pub async fn get_scheduling_failure(&self, podname: &str) -> Result<Option<String>> {
+        let lp = ListParams::default().fields(&format!(
+            "involvedObject.kind=Pod,involvedObject.name={podname},reason=FailedScheduling"
+        ));
+        let events = self.ns::<Event>().list(&lp).await.map_err(Error::Kube)?;
+        let latest = events.items.into_iter().max_by_key(|e| {
+            let ts = e.last_timestamp.as_ref().map(|t| t.0);
+            ts.or_else(|| e.event_time.as_ref().map(|t| t.0))
+        });
+        Ok(latest.and_then(|e| e.message))
+    }
+}
+
+/// Conditions that are not as expected on a healthy node
+fn node_problems(node: &Node) -> Vec<String> {
+    let conditions = node.status.as_ref().and_then(|s| s.conditions.clone());
+    conditions
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|c| match c.type_.as_str() {
+            "Ready" => c.status != "True",
+            _ => c.status == "True", // pressure conditions, NetworkUnavailable
+        })
+        .map(|c| match (c.type_.as_str(), &c.reason) {
+            ("Ready", Some(reason)) => format!("Ready={} ({reason})", c.status),
+            ("Ready", None) => format!("Ready={}", c.status),
+            (t, Some(reason)) => format!("{t} ({reason})"),
+            (t, None) => t.to_string(),
+        })
+        .collect()
+}
+
+/// Hard taints (NoSchedule, NoExecute) not matched by any toleration
+fn untolerated(taints: &[Taint], tolerations: &[Toleration]) -> Vec<String> {
+    taints
+        .iter()
+        .filter(|t| t.effect != "PreferNoSchedule")
+        .filter(|t| !tolerations.iter().any(|tol| tolerates(tol, t)))
+        .map(|t| match &t.value {
+            Some(v) => format!("{}={v}:{}", t.key, t.effect),
+            None => format!("{}:{}", t.key, t.effect),
+        })
+        .collect()
+}
+
+fn tolerates(tol: &Toleration, taint: &Taint) -> bool {
+    if tol
+        .effect
+        .as_ref()
+        .is_some_and(|e| !e.is_empty() && *e != taint.effect)
+    {
+        return false;
+    }
+    let key = tol.key.as_deref().unwrap_or_default();
+    match tol.operator.as_deref() {
+        Some("Exists") => key.is_empty() || key == taint.key,
+        _ => {
+            key == taint.key
+                && tol.value.as_deref().unwrap_or_default() == taint.value.as_deref().unwrap_or_default()
+        }
+    }
+}
+
+/// Container requests of running pods summed against node allocatable
+fn resource_usage(node: &Node, pods: &[Pod]) -> Vec<ResourceUsage> {
+    let allocatable = node.status.as_ref().and_then(|s| s.allocatable.clone());
+    let allocatable = allocatable.unwrap_or_default();
+    let active = pods.iter().filter(|p| {
+        let phase = p.status.as_ref().and_then(|s| s.phase.as_deref());
+        !matches!(phase, Some("Succeeded") | Some("Failed"))
+    });
+    let mut requested = BTreeMap::<&str, f64>::new();
+    for pod in active {
+        *requested.entry("pods").or_default() += 1.0;
+        let containers = pod.spec.iter().flat_map(|s| &s.containers);
+        for c in containers {
+            let requests = c.resources.as_ref().and_then(|r| r.requests.as_ref());
+            for (res, q) in requests.into_iter().flatten() {
+                *requested.entry(res.as_str()).or_default() += parse_quantity(q).unwrap_or_default();
+            }
+        }
+    }
+    ["cpu", "memory", "pods"]
+        .into_iter()
+        .filter_map(|res| {
+            Some(ResourceUsage {
+                resource: res.to_string(),
+                allocatable: parse_quantity(allocatable.get(res)?)?,
+                requested: requested.get(res).copied().unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Parse a resource quantity into base units (cores, bytes or count)
+fn parse_quantity(q: &Quantity) -> Option<f64> {
+    let s = q.0.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (num, suffix) = s.split_at(split);
+    let factor = match suffix {
+        "" => 1.0,
+        "m" => 1e-3,
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        "Ki" => 1024.0,
+        "Mi" => 1024.0_f64.powi(2),
+        "Gi" => 1024.0_f64.powi(3),
+        "Ti" => 1024.0_f64.powi(4),
+        _ => return None,
+    };
+    Some(num.parse::<f64>().ok()? * factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    #[test]
+    fn node_context() {
+        let node: Node = testing::object(json!({
+            "metadata": { "name": "node-1" },
+            "spec": { "taints": [
+                { "key": "dedicated", "value": "gpu", "effect": "NoSchedule" },
+                { "key": "node.kubernetes.io/disk-pressure", "effect": "NoSchedule" },
+                { "key": "spot", "effect": "PreferNoSchedule" }
+            ]},
+            "status": {
+                "allocatable": { "cpu": "3920m", "memory": "2Gi", "pods": "2" },
+                "conditions": [
+                    { "type": "Ready", "status": "True" },
+                    { "type": "DiskPressure", "status": "True", "reason": "KubeletHasDiskPressure" },
+                    { "type": "MemoryPressure", "status": "False" }
+                ]
+            }
+        }));
+        let pod = testing::pod(json!({
+            "spec": {
+                "containers": [{ "name": "app", "resources": { "requests": { "cpu": "1.5", "memory": "512Mi" } } }],
+                "tolerations": [{ "key": "dedicated", "operator": "Equal", "value": "gpu" }]
+            }
+        }));
+
+        assert_eq!(node_problems(&node), ["DiskPressure (KubeletHasDiskPressure)"]);
+        let taints = node.spec.clone().unwrap().taints.unwrap();
+        let tolerations = pod.spec.clone().unwrap().tolerations.unwrap();
+        assert_eq!(untolerated(&taints, &tolerations), [
+            "node.kubernetes.io/disk-pressure:NoSchedule"
+        ]);
+
+        let usage = resource_usage(&node, &[pod.clone(), pod])
+            .iter()
+            .map(|u| u.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(usage, ["cpu 3.00/3.92", "memory 1024Mi/2048Mi", "pods 2/2"]);
+    }
+}
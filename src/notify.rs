@@ -0,0 +1,294 @@
+//! webhook notifications on rollout start, finish and failure
+use crate::{Error, Result, Rollout, Verdict};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Point in a rollout that triggers a notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// Tracking of the rollout started
+    Started,
+    /// The rollout concluded successfully
+    Finished,
+    /// The rollout concluded unsuccessfully (or could not be tracked)
+    Failed,
+}
+
+/// The content of a notification
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub event: Event,
+    /// Workload as namespace/kind/name (prefixed by cluster: when labelled)
+    pub workload: String,
+    /// Version of the tracked revision (main container image tag)
+    pub version: Option<String>,
+    /// Seconds spent tracking the rollout
+    pub duration: Option<u64>,
+    /// How the rollout concluded
+    pub outcome: Option<String>,
+    /// Summary of the diagnosis of a failure
+    pub diagnosis: Option<String>,
+}
+
+impl Notification {
+    /// A notification for a rollout that started tracking
+    pub fn started(r: &Rollout) -> Self {
+        Notification {
+            event: Event::Started,
+            workload: r.workload_ref(),
+            version: None,
+            duration: None,
+            outcome: None,
+            diagnosis: None,
+        }
+    }
+
+    /// A notification for a rollout that concluded
+    pub fn finished(r: &Rollout, verdict: &Verdict, duration: Duration) -> Self {
+        Notification {
+            event: if verdict.is_success() {
+                Event::Finished
+            } else {
+                Event::Failed
+            },
+            duration: Some(duration.as_secs()),
+            outcome: Some(verdict.to_string()),
+            ..Self::started(r)
+        }
+    }
+
+    /// A notification for a rollout that could not be tracked
+    pub fn errored(r: &Rollout, err: &Error, duration: Duration) -> Self {
+        Notification {
+            event: Event::Failed,
+            duration: Some(duration.as_secs()),
+            outcome: Some(err.to_string()),
+            ..Self::started(r)
+        }
+    }
+
+    fn text(&self) -> String {
+        let icon = match self.event {
+            Event::Started => ":rocket:",
+            Event::Finished => ":white_check_mark:",
+            Event::Failed => ":x:",
+        };
+        let mut text = format!("{icon} {}", self.workload);
+        if let Some(v) = &self.version {
+            text += &format!(" {v}");
+        }
+        match &self.outcome {
+            Some(o) => text += &format!(" {o}"),
+            None => text += " rolling out",
+        }
+        if let Some(d) = self.duration {
+            text += &format!(" after {d}s");
+        }
+        if let Some(diag) = &self.diagnosis {
+            text += &format!("\n{diag}");
+        }
+        text
+    }
+}
+
+/// Payload format for a webhook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// The `Notification` as json
+    Generic,
+    /// Slack compatible incoming webhook message
+    Slack,
+    /// Custom json template
+    ///
+    /// Placeholders `{event}`, `{workload}`, `{version}`, `{duration}`, `{outcome}` and `{diagnosis}`
+    /// are replaced with json escaped values (without quotes).
+    Template(String),
+}
+
+/// A webhook to POST notifications to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default = "default_format")]
+    pub format: Format,
+    /// Events to notify about (all if empty)
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+fn default_format() -> Format {
+    Format::Generic
+}
+
+impl Webhook {
+    /// A webhook posting the generic format for all events
+    pub fn new(url: impl Into<String>) -> Self {
+        Webhook {
+            url: url.into(),
+            format: Format::Generic,
+            events: vec![],
+        }
+    }
+
+    /// Render the payload for a notification
+    pub fn payload(&self, n: &Notification) -> Result<Value> {
+        match &self.format {
+            Format::Generic => serde_json::to_value(n).map_err(Error::Serialization),
+            Format::Slack => Ok(json!({ "text": n.text() })),
+            Format::Template(tpl) => {
+                let escape = |s: &str| {
+                    let quoted = Value::String(s.to_string()).to_string();
+                    quoted[1..quoted.len() - 1].to_string()
+                };
+                let event = serde_json::to_value(n.event).map_err(Error::Serialization)?;
+                let body = tpl
+                    .replace("{event}", event.as_str().unwrap_or_default())
+                    .replace("{workload}", &escape(&n.workload))
+                    .replace("{version}", &escape(n.version.as_deref().unwrap_or_default()))
+                    .replace("{duration}", &n.duration.unwrap_or_default().to_string())
+                    .replace("{outcome}", &escape(n.outcome.as_deref().unwrap_or_default()))
+                    .replace("{diagnosis}", &escape(n.diagnosis.as_deref().unwrap_or_default()));
+                serde_json::from_str(&body).map_err(Error::Serialization)
+            }
+        }
+    }
+}
+
+/// Sends notifications to a set of webhooks
+#[derive(Clone)]
+pub struct Notifier {
+    client: reqwest::Client,
+    hooks: Vec<Webhook>,
+    /// Number of retries for failed deliveries
+    pub retries: u32,
+    /// Delay before the first retry (doubled for each subsequent retry)
+    pub backoff: Duration,
+}
+
+impl Notifier {
+    pub fn new(hooks: Vec<Webhook>) -> Self {
+        Notifier {
+            client: reqwest::Client::new(),
+            hooks,
+            retries: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Deliver a notification to all interested webhooks
+    ///
+    /// Delivery failures are logged rather than propagated; a broken webhook
+    /// should not fail the rollout it reports on.
+    pub async fn notify(&self, n: &Notification) {
+        for hook in &self.hooks {
+            if !hook.events.is_empty() && !hook.events.contains(&n.event) {
+                continue;
+            }
+            if let Err(e) = self.send(hook, n).await {
+                warn!("Failed to notify {}: {e}", hook.url);
+            }
+        }
+    }
+
+    async fn send(&self, hook: &Webhook, n: &Notification) -> Result<()> {
+        let payload = hook.payload(n)?;
+        let mut delay = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let res = self.client.post(&hook.url).json(&payload).send().await;
+            let err = match res.and_then(|r| r.error_for_status()) {
+                Ok(_) => return Ok(()),
+                Err(e) => e,
+            };
+            if attempt >= self.retries {
+                return Err(Error::Notify(err));
+            }
+            attempt += 1;
+            debug!("Retrying {} in {delay:?} after: {err}", hook.url);
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use std::sync::{Arc, Mutex};
+
+    type Received = Arc<Mutex<Vec<Value>>>;
+
+    /// Local webhook stand-in that fails the first request
+    async fn standin() -> (String, Received) {
+        let received = Received::default();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(|State(rx): State<Received>, Json(body): Json<Value>| async move {
+                    let mut rx = rx.lock().unwrap();
+                    rx.push(body);
+                    if rx.len() == 1 {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                }),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    fn failure() -> Notification {
+        Notification {
+            event: Event::Failed,
+            workload: "shop/Deployment/checkout".into(),
+            version: Some("1.4.2".into()),
+            duration: Some(42),
+            outcome: Some("timed out".into()),
+            diagnosis: Some("crash looping: 0/2 pods \"ready\"".into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_with_retries() {
+        let (url, received) = standin().await;
+        let mut notifier = Notifier::new(vec![Webhook::new(url)]);
+        notifier.backoff = Duration::from_millis(1);
+        notifier.send(&notifier.hooks[0], &failure()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2, "first attempt fails and is retried");
+        assert_eq!(received[1]["workload"], "shop/Deployment/checkout");
+        assert_eq!(received[1]["event"], "failed");
+        assert_eq!(received[1]["duration"], 42);
+    }
+
+    #[test]
+    fn formats() {
+        let mut hook = Webhook::new("http://localhost");
+        hook.format = Format::Slack;
+        let slack = hook.payload(&failure()).unwrap();
+        assert!(slack["text"]
+            .as_str()
+            .unwrap()
+            .starts_with(":x: shop/Deployment/checkout 1.4.2 timed out"));
+
+        hook.format =
+            Format::Template(r#"{"msg": "{workload} {outcome}: {diagnosis}", "secs": {duration}}"#.into());
+        let custom = hook.payload(&failure()).unwrap();
+        assert_eq!(
+            custom["msg"],
+            "shop/Deployment/checkout timed out: crash looping: 0/2 pods \"ready\""
+        );
+        assert_eq!(custom["secs"], 42);
+    }
+}
@@ -0,0 +1,102 @@
+//! cluster policies that interact with a rollout (autoscalers and disruption budgets)
+use crate::{Error, Kind, Result, Rollout, State};
+
+use k8s_openapi::api::{autoscaling::v2::HorizontalPodAutoscaler, policy::v1::PodDisruptionBudget};
+use kube::{
+    api::ListParams,
+    core::{Selector, SelectorExt},
+    ResourceExt,
+};
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+impl Rollout {
+    /// Find the HorizontalPodAutoscaler that scales this workload (if any)
+    pub async fn get_autoscaler(&self) -> Result<Option<HorizontalPodAutoscaler>> {
+        if matches!(self.workload, Kind::DaemonSet | Kind::Job) {
+            return Ok(None); // daemonsets and jobs are not scalable
+        }
+        let hpas = self
+            .ns()
+            .list(&ListParams::default())
+            .await
+            .map_err(Error::Kube)?;
+        Ok(hpas
+            .into_iter()
+            .find(|hpa| autoscaler_targets(hpa, &self.workload, &self.name)))
+    }
+
+    /// Update the replica count we wait for from the autoscaler found at start
+    ///
+    /// An autoscaler can rescale the workload mid-rollout, so the target moves with it.
+    pub async fn follow_autoscaler(&self, state: &mut State) -> Result<()> {
+        let Some(name) = &state.autoscaler else {
+            return Ok(());
+        };
+        let hpa: HorizontalPodAutoscaler = self.ns().get(name).await.map_err(Error::Kube)?;
+        if let Some(desired) = autoscaler_desired(&hpa) {
+            if desired != state.min_replicas {
+                info!(
+                    "{} rescaled by {name}: {} -> {desired}",
+                    self.name, state.min_replicas
+                );
+                state.min_replicas = desired;
+            }
+        }
+        Ok(())
+    }
+
+    /// Find PodDisruptionBudgets covering the tracked pods that allow no disruptions
+    ///
+    /// These will block evictions (e.g. node drains) of the pods, and can stall a rollout.
+    pub async fn get_blocking_disruption_budgets(
+        &self,
+        selector: &Selector,
+    ) -> Result<Vec<PodDisruptionBudget>> {
+        let pods = self.get_pods(selector).await?;
+        let pdbs = self
+            .ns::<PodDisruptionBudget>()
+            .list(&ListParams::default())
+            .await
+            .map_err(Error::Kube)?;
+        let mut blocking = vec![];
+        for pdb in pdbs {
+            if disruptions_allowed(&pdb) != Some(0) {
+                continue;
+            }
+            let Some(pdb_selector) = pdb_selector(&pdb) else {
+                continue;
+            };
+            if pods.iter().any(|p| pdb_selector.matches(p.labels())) {
+                blocking.push(pdb);
+            }
+        }
+        Ok(blocking)
+    }
+}
+
+fn autoscaler_targets(hpa: &HorizontalPodAutoscaler, kind: &Kind, name: &str) -> bool {
+    let Some(spec) = &hpa.spec else {
+        return false;
+    };
+    let target = &spec.scale_target_ref;
+    target.kind == kind.to_string() && target.name == name
+}
+
+fn autoscaler_desired(hpa: &HorizontalPodAutoscaler) -> Option<u32> {
+    let status = hpa.status.as_ref()?;
+    // NB: desired_replicas is 0 before the autoscaler has done its first calculation
+    if status.desired_replicas > 0 {
+        status.desired_replicas.try_into().ok()
+    } else {
+        None
+    }
+}
+
+fn disruptions_allowed(pdb: &PodDisruptionBudget) -> Option<i32> {
+    pdb.status.as_ref().map(|s| s.disruptions_allowed)
+}
+
+fn pdb_selector(pdb: &PodDisruptionBudget) -> Option<Selector> {
+    let ls = pdb.spec.as_ref()?.selector.clone()?;
+    ls.try_into().ok()
+}
@@ -0,0 +1,223 @@
+//! checks on what the workload controller made of an apply before tracking it
+use crate::{rollout::REVISION_ANNOTATION, Error, Kind, Result, Rollout};
+
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet},
+    batch::v1::Job,
+};
+use kube::{api::ListParams, core::Selector, ResourceExt};
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// What the workload controller has made of the latest apply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Preflight {
+    /// The controller has not yet observed the latest generation of the spec
+    Unobserved { generation: i64, observed: i64 },
+    /// The deployment is paused and will not roll out until resumed
+    Paused,
+    /// The apply did not change the pod template and the current revision is fully rolled out
+    Unchanged,
+    /// A new revision is rolling out
+    Progressing,
+}
+
+impl std::fmt::Display for Preflight {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Preflight::Unobserved { generation, .. } => {
+                write!(f, "waiting for controller to observe generation {generation}")
+            }
+            Preflight::Paused => write!(f, "paused"),
+            Preflight::Unchanged => write!(f, "no change"),
+            Preflight::Progressing => write!(f, "progressing"),
+        }
+    }
+}
+
+impl Rollout {
+    /// Check whether there is a rollout to track at all
+    ///
+    /// Compares the workload generation with the observed generation, then checks
+    /// whether the workload is paused, and whether a new revision is actually rolling out.
+    pub async fn preflight(&self) -> Result<Preflight> {
+        match self.workload {
+            Kind::Deployment => {
+                let d = self.get_deploy().await?;
+                let Some(spec) = &d.spec else {
+                    return Err(Error::KubeInvariant("Missing deployment spec".to_string()));
+                };
+                let selector: Selector = spec
+                    .selector
+                    .clone()
+                    .try_into()
+                    .map_err(|e| Error::KubeInvariant(format!("malformed label selector: {e}")))?;
+                let lp = ListParams::default().labels_from(&selector);
+                let sets = self.ns::<ReplicaSet>().list(&lp).await.map_err(Error::Kube)?;
+                Ok(deploy_preflight(&d, &sets.items))
+            }
+            Kind::StatefulSet => Ok(statefulset_preflight(&self.get_statefulset().await?)),
+            Kind::DaemonSet => Ok(daemonset_preflight(&self.get_daemonset().await?)),
+            Kind::Job => Ok(job_preflight(&self.get_job().await?)),
+        }
+    }
+}
+
+fn unobserved(generation: Option<i64>, observed: Option<i64>) -> Option<Preflight> {
+    let generation = generation.unwrap_or(0);
+    let observed = observed.unwrap_or(0);
+    (observed < generation).then_some(Preflight::Unobserved { generation, observed })
+}
+
+fn deploy_preflight(d: &Deployment, sets: &[ReplicaSet]) -> Preflight {
+    let status = d.status.clone().unwrap_or_default();
+    if let Some(p) = unobserved(d.metadata.generation, status.observed_generation) {
+        return p;
+    }
+    let spec = d.spec.clone().unwrap_or_default();
+    if spec.paused == Some(true) {
+        return Preflight::Paused;
+    }
+    // The new replicaset shares the revision number of the deployment
+    let revision = d.annotations().get(REVISION_ANNOTATION);
+    let (new, old): (Vec<_>, Vec<_>) = sets
+        .iter()
+        .partition(|rs| revision.is_some() && rs.annotations().get(REVISION_ANNOTATION) == revision);
+    let Some(new_rs) = new.first() else {
+        return Preflight::Progressing; // controller has yet to create it
+    };
+    let desired = spec.replicas.unwrap_or(1);
+    let new_ready = new_rs.status.as_ref().and_then(|s| s.ready_replicas).unwrap_or(0);
+    let old_replicas: i32 = old
+        .iter()
+        .filter_map(|rs| rs.status.as_ref())
+        .map(|s| s.replicas)
+        .sum();
+    let updated = status.updated_replicas.unwrap_or(0);
+    let available = status.available_replicas.unwrap_or(0);
+    if old_replicas == 0 && new_ready >= desired && updated == desired && available >= desired {
+        Preflight::Unchanged
+    } else {
+        Preflight::Progressing
+    }
+}
+
+fn statefulset_preflight(sts: &StatefulSet) -> Preflight {
+    let status = sts.status.clone().unwrap_or_default();
+    if let Some(p) = unobserved(sts.metadata.generation, status.observed_generation) {
+        return p;
+    }
+    let desired = sts.spec.as_ref().and_then(|s| s.replicas).unwrap_or(1);
+    let same_revision =
+        status.current_revision.is_some() && status.current_revision == status.update_revision;
+    let updated = status.updated_replicas.unwrap_or(0);
+    let ready = status.ready_replicas.unwrap_or(0);
+    if same_revision && updated == desired && ready == desired {
+        Preflight::Unchanged
+    } else {
+        Preflight::Progressing
+    }
+}
+
+fn job_preflight(job: &Job) -> Preflight {
+    if job.spec.as_ref().and_then(|s| s.suspend) == Some(true) {
+        return Preflight::Paused;
+    }
+    let conditions = job.status.as_ref().and_then(|s| s.conditions.clone());
+    let complete = conditions
+        .unwrap_or_default()
+        .iter()
+        .any(|c| c.type_ == "Complete" && c.status == "True");
+    if complete {
+        Preflight::Unchanged // jobs are immutable, so an apply cannot have changed a completed one
+    } else {
+        Preflight::Progressing
+    }
+}
+
+fn daemonset_preflight(ds: &DaemonSet) -> Preflight {
+    let status = ds.status.clone().unwrap_or_default();
+    if let Some(p) = unobserved(ds.metadata.generation, status.observed_generation) {
+        return p;
+    }
+    let desired = status.desired_number_scheduled;
+    let updated = status.updated_number_scheduled.unwrap_or(0);
+    let available = status.number_available.unwrap_or(0);
+    if updated == desired && available == desired {
+        Preflight::Unchanged
+    } else {
+        Preflight::Progressing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    fn deploy(generation: i64, observed: i64, paused: bool) -> Deployment {
+        testing::deployment(json!({
+            "metadata": {
+                "generation": generation,
+                "annotations": { REVISION_ANNOTATION: "2" }
+            },
+            "spec": { "paused": paused },
+            "status": { "observedGeneration": observed }
+        }))
+    }
+
+    fn rs(revision: &str, replicas: i32) -> ReplicaSet {
+        testing::replicaset(json!({
+            "metadata": {
+                "name": format!("web-{revision}"),
+                "annotations": { REVISION_ANNOTATION: revision }
+            },
+            "status": { "replicas": replicas, "readyReplicas": replicas }
+        }))
+    }
+
+    #[test]
+    fn deploy_preflight_states() {
+        let sets = [rs("1", 0), rs("2", 2)];
+        assert_eq!(
+            deploy_preflight(&deploy(3, 2, false), &sets),
+            Preflight::Unobserved {
+                generation: 3,
+                observed: 2
+            }
+        );
+        assert_eq!(deploy_preflight(&deploy(3, 3, true), &sets), Preflight::Paused);
+        assert_eq!(
+            deploy_preflight(&deploy(3, 3, false), &sets),
+            Preflight::Unchanged
+        );
+        let surging = [rs("1", 1), rs("2", 2)];
+        assert_eq!(
+            deploy_preflight(&deploy(3, 3, false), &surging),
+            Preflight::Progressing
+        );
+        assert_eq!(
+            deploy_preflight(&deploy(3, 3, false), &[rs("1", 2)]),
+            Preflight::Progressing
+        );
+    }
+
+    fn job(suspend: bool, condition: Option<&str>) -> Job {
+        let conditions: Vec<_> = condition
+            .map(|type_| json!({ "type": type_, "status": "True" }))
+            .into_iter()
+            .collect();
+        testing::job(json!({
+            "spec": { "suspend": suspend },
+            "status": { "conditions": conditions }
+        }))
+    }
+
+    #[test]
+    fn job_preflight_states() {
+        assert_eq!(job_preflight(&job(true, None)), Preflight::Paused);
+        assert_eq!(job_preflight(&job(false, Some("Complete"))), Preflight::Unchanged);
+        assert_eq!(job_preflight(&job(false, None)), Preflight::Progressing);
+        assert_eq!(job_preflight(&job(false, Some("Failed"))), Preflight::Progressing);
+    }
+}
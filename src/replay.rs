@@ -0,0 +1,519 @@
+//! replaying recorded api responses through a kube client
+//!
+//! A recording is a list of timestamped responses to api requests (stored as json lines),
+//! preceded by the parameters of each tracked rollout.
+//! `Recorder` captures them from a live client, and `Replay` serves them as a tower service,
+//! so tracking logic can run against captured objects.
+use crate::{
+    config::Target, Error, Outcome, PollStrategy, Result, Rollout, State, SupersedePolicy, VersionScheme,
+    Versioning,
+};
+
+use http::{Request, Response};
+use kube::client::Body;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use time::OffsetDateTime;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// A recorded api response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    /// When the response was received
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+    /// Request method
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// Request path including the query string
+    pub path: String,
+    /// Response status code
+    #[serde(default = "default_status")]
+    pub status: u16,
+    /// Response body (a string for non-json responses such as logs)
+    pub body: serde_json::Value,
+    /// Kubeconfig context the request was sent to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// Parameters a rollout was tracked with, recorded ahead of its responses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tracking {
+    /// Workload as `[context:]namespace/Kind/name`
+    pub workload: String,
+    /// Maximum seconds to wait for the rollout (estimated from the workload if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Name of the main container
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Version or image digest the ready pods must run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_version: Option<String>,
+    /// Label with the version of a revision
+    pub version_label: String,
+    /// How versions are ordered to find the newest revision
+    pub version_scheme: VersionScheme,
+    /// What to do when a newer revision replaces the one being tracked
+    pub on_supersede: SupersedePolicy,
+}
+
+impl From<&Rollout> for Tracking {
+    fn from(r: &Rollout) -> Self {
+        Tracking {
+            workload: r.workload_ref(),
+            timeout: r.timeout.map(|t| t.as_secs()),
+            container: r.container.clone(),
+            expect_version: r.expect_version.clone(),
+            version_label: r.versioning.label.clone(),
+            version_scheme: r.versioning.scheme,
+            on_supersede: r.on_supersede,
+        }
+    }
+}
+
+/// A line of a recording
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Line {
+    Rollout { rollout: Tracking },
+    Exchange(Exchange),
+}
+
+fn lines(jsonl: &str) -> Result<Vec<Line>> {
+    let lines = jsonl.lines().filter(|l| !l.trim().is_empty());
+    lines
+        .map(|l| {
+            let value: serde_json::Value = serde_json::from_str(l).map_err(Error::Serialization)?;
+            match value.get("rollout") {
+                Some(rollout) => Ok(Line::Rollout {
+                    rollout: serde_json::from_value(rollout.clone()).map_err(Error::Serialization)?,
+                }),
+                None => {
+                    let exchange: Exchange = serde_json::from_value(value).map_err(Error::Serialization)?;
+                    // responses are rebuilt from the status, so it has to be a valid one
+                    if http::StatusCode::from_u16(exchange.status).is_err() {
+                        warn!(
+                            "invalid status {} recorded for {}",
+                            exchange.status, exchange.path
+                        );
+                        return Err(Error::IllegalDocument);
+                    }
+                    Ok(Line::Exchange(exchange))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Parse the responses of a recording from json lines
+pub fn parse(jsonl: &str) -> Result<Vec<Exchange>> {
+    let exchanges = lines(jsonl)?.into_iter().filter_map(|l| match l {
+        Line::Exchange(e) => Some(e),
+        Line::Rollout { .. } => None,
+    });
+    Ok(exchanges.collect())
+}
+
+/// Parse the tracked rollouts of a recording from json lines, in the order they were tracked
+pub fn rollouts(jsonl: &str) -> Result<Vec<Tracking>> {
+    let rollouts = lines(jsonl)?.into_iter().filter_map(|l| match l {
+        Line::Rollout { rollout } => Some(rollout),
+        Line::Exchange(_) => None,
+    });
+    Ok(rollouts.collect())
+}
+
+/// Writes the responses to GET requests of wrapped clients as json lines
+///
+/// Cheap to clone; all clones write to the same file.
+#[derive(Clone)]
+pub struct Recorder {
+    out: Arc<Mutex<File>>,
+}
+
+impl Recorder {
+    /// Record into a new file (truncating any existing one)
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(Error::Io)?;
+        Ok(Recorder {
+            out: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// A client that records what it fetches through `client`, labelled with its context
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn client(&self, client: kube::Client, context: Option<&str>) -> kube::Client {
+        let namespace = client.default_namespace().to_string();
+        let (recorder, context) = (self.clone(), context.map(String::from));
+        let service = tower::service_fn(move |req: Request<Body>| {
+            let (client, recorder, context) = (client.clone(), recorder.clone(), context.clone());
+            async move {
+                let method = req.method().to_string();
+                let path = req.uri().path_and_query().map_or("", |p| p.as_str()).to_string();
+                let res = client.send(req).await?;
+                if method != "GET" {
+                    return Ok::<_, kube::Error>(res); // only fetches matter for replays
+                }
+                let (parts, body) = res.into_parts();
+                let bytes = body.collect_bytes().await?;
+                let body = serde_json::from_slice(&bytes)
+                    .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).into_owned().into());
+                recorder.write(&Line::Exchange(Exchange {
+                    time: OffsetDateTime::now_utc(),
+                    method,
+                    path,
+                    status: parts.status.as_u16(),
+                    body,
+                    context,
+                }));
+                Ok(Response::from_parts(parts, Body::from(bytes)))
+            }
+        });
+        kube::Client::new(service, namespace)
+    }
+
+    /// Record the parameters a rollout is tracked with, so it can be replayed with them
+    pub fn track(&self, r: &Rollout) {
+        let rollout = Tracking::from(r);
+        self.write(&Line::Rollout { rollout });
+    }
+
+    fn write(&self, line: &Line) {
+        let what = match line {
+            Line::Rollout { rollout } => &rollout.workload,
+            Line::Exchange(exchange) => &exchange.path,
+        };
+        // custom version schemes cannot be serialized
+        let mut json = match serde_json::to_string(line) {
+            Ok(json) => json,
+            Err(e) => return warn!("failed to record {what}: {e}"),
+        };
+        json.push('\n');
+        let mut out = self.out.lock().unwrap();
+        if let Err(e) = out.write_all(json.as_bytes()) {
+            warn!("failed to record {what}: {e}");
+        }
+    }
+}
+
+/// Which recorded response a request is answered with
+enum Clock {
+    /// The latest one at a point in time
+    At(OffsetDateTime),
+    /// The next one for the request in recorded order (repeating the last), by requests served so far
+    Sequence(HashMap<(String, String), usize>),
+}
+
+/// Recorded responses served as a kube api, at a movable point in time or in recorded order
+#[derive(Clone)]
+pub struct Replay {
+    exchanges: Arc<Vec<Exchange>>,
+    clock: Arc<Mutex<Clock>>,
+}
+
+impl Replay {
+    /// Serve a recording, starting at its first response
+    pub fn new(mut exchanges: Vec<Exchange>) -> Self {
+        exchanges.sort_by_key(|e| e.time);
+        let start = exchanges.first().map_or(OffsetDateTime::UNIX_EPOCH, |e| e.time);
+        Replay {
+            exchanges: Arc::new(exchanges),
+            clock: Arc::new(Mutex::new(Clock::At(start))),
+        }
+    }
+
+    /// Serve a recorded session, answering each request with the next response recorded for it
+    ///
+    /// Code that makes the same requests as during recording (e.g. `Rollout::track`) sees the same
+    /// responses, regardless of how long it waits between them.
+    pub fn session(exchanges: Vec<Exchange>) -> Self {
+        let replay = Replay::new(exchanges);
+        *replay.clock.lock().unwrap() = Clock::Sequence(HashMap::new());
+        replay
+    }
+
+    /// Distinct times at which responses were recorded, in order
+    pub fn times(&self) -> Vec<OffsetDateTime> {
+        let mut times = self.exchanges.iter().map(|e| e.time).collect::<Vec<_>>();
+        times.dedup();
+        times
+    }
+
+    /// Serve responses as they were at `time`
+    pub fn set_time(&self, time: OffsetDateTime) {
+        *self.clock.lock().unwrap() = Clock::At(time);
+    }
+
+    /// A client for the recorded api, defaulting to the given namespace
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn client(&self, namespace: &str) -> kube::Client {
+        let replay = self.clone();
+        let service = tower::service_fn(move |req: Request<Body>| {
+            let path = req.uri().path_and_query().map_or("", |p| p.as_str());
+            let res = replay.respond(req.method().as_str(), path);
+            async move { Ok::<_, std::convert::Infallible>(res) }
+        });
+        kube::Client::new(service, namespace)
+    }
+
+    /// A rollout with recorded parameters, using a client from this replay
+    ///
+    /// Polls without waiting, and as often as there are responses to answer them with.
+    pub fn rollout(&self, tracking: &Tracking) -> Result<Rollout> {
+        let target: Target = tracking.workload.parse()?;
+        let client = self.client(target.namespace.as_deref().unwrap_or("default"));
+        Ok(Rollout {
+            name: target.name,
+            namespace: target.namespace,
+            workload: target.kind,
+            on_supersede: tracking.on_supersede,
+            timeout: tracking.timeout.map(Duration::from_secs),
+            container: tracking.container.clone(),
+            expect_version: tracking.expect_version.clone(),
+            versioning: Versioning {
+                label: tracking.version_label.clone(),
+                scheme: tracking.version_scheme,
+            },
+            poll: PollStrategy::Immediately(self.exchanges.len().try_into().unwrap_or(u64::MAX)),
+            client,
+            cluster: target.context,
+        })
+    }
+
+    /// Poll the status of a rollout at every recorded point in time
+    ///
+    /// The rollout must use a client from this replay. Its revision is pinned at the first response.
+    pub async fn outcomes(&self, r: &Rollout) -> Result<Vec<Outcome>> {
+        let times = self.times();
+        self.set_time(times.first().copied().unwrap_or(OffsetDateTime::UNIX_EPOCH));
+        let params = r.infer_parameters().await?;
+        let mut state = State::new(&params)?;
+        r.pin(&mut state).await?;
+        let mut outcomes = vec![];
+        for time in times {
+            self.set_time(time);
+            outcomes.push(r.status(&state).await?);
+        }
+        Ok(outcomes)
+    }
+
+    /// The recorded response for a request according to the clock
+    ///
+    /// Requests that were first recorded later get their first response (the objects already existed).
+    fn respond(&self, method: &str, path: &str) -> Response<Body> {
+        let recorded = self
+            .exchanges
+            .iter()
+            .filter(|e| e.method == method && e.path == path)
+            .collect::<Vec<_>>();
+        let found = match &mut *self.clock.lock().unwrap() {
+            Clock::At(now) => recorded.iter().rfind(|e| e.time <= *now).or(recorded.first()),
+            Clock::Sequence(served) => {
+                let n = served.entry((method.to_string(), path.to_string())).or_default();
+                *n += 1;
+                recorded.get(*n - 1).or(recorded.last())
+            }
+        };
+        let (status, body) = match found {
+            Some(e) => (e.status, e.body.clone()),
+            None => {
+                debug!("no recorded response for {method} {path}");
+                let message = format!("{method} {path} was not recorded");
+                (404, not_found(&message))
+            }
+        };
+        let body = match body {
+            serde_json::Value::String(text) => text.into_bytes(),
+            json => serde_json::to_vec(&json).expect("json values serialize"),
+        };
+        Response::builder()
+            .status(status)
+            .body(Body::from(body))
+            .expect("valid response")
+    }
+}
+
+fn not_found(message: &str) -> serde_json::Value {
+    json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "status": "Failure",
+        "message": message,
+        "reason": "NotFound",
+        "code": 404,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Kind;
+
+    fn rollout(workload: Kind, name: &str, client: kube::Client) -> Rollout {
+        Rollout {
+            name: name.into(),
+            namespace: Some("shop".into()),
+            workload,
+            on_supersede: SupersedePolicy::Follow,
+            client,
+            cluster: None,
+            timeout: None,
+            container: None,
+            expect_version: None,
+            versioning: Default::default(),
+            poll: Default::default(),
+        }
+    }
+
+    /// Replay a fixture recording of a rollout and poll it at every recorded time
+    async fn replay(fixture: &str, workload: Kind, name: &str) -> Vec<Outcome> {
+        let replay = Replay::new(parse(fixture).unwrap());
+        replay
+            .outcomes(&rollout(workload, name, replay.client("shop")))
+            .await
+            .unwrap()
+    }
+
+    fn progress(outcomes: &[Outcome]) -> Vec<(u32, u32, bool)> {
+        outcomes.iter().map(|o| (o.progress, o.expected, o.ok)).collect()
+    }
+
+    #[tokio::test]
+    async fn replays_deployment() {
+        let outcomes = replay(
+            include_str!("../fixtures/deployment.jsonl"),
+            Kind::Deployment,
+            "web",
+        )
+        .await;
+        assert_eq!(progress(&outcomes), [
+            (0, 3, false),
+            (1, 3, false),
+            (2, 3, false),
+            (3, 3, true)
+        ]);
+        let message = outcomes.last().and_then(|o| o.message.as_deref());
+        assert_eq!(
+            message,
+            Some("ReplicaSet \"web-7c9d8f\" has successfully progressed.")
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_statefulset() {
+        let outcomes = replay(
+            include_str!("../fixtures/statefulset.jsonl"),
+            Kind::StatefulSet,
+            "db",
+        )
+        .await;
+        // all pods are updated before the last one is ready
+        assert_eq!(progress(&outcomes), [
+            (1, 3, false),
+            (2, 3, false),
+            (3, 3, false),
+            (3, 3, true)
+        ]);
+    }
+
+    #[tokio::test]
+    async fn replays_daemonset() {
+        let outcomes = replay(
+            include_str!("../fixtures/daemonset.jsonl"),
+            Kind::DaemonSet,
+            "agent",
+        )
+        .await;
+        assert_eq!(progress(&outcomes), [(0, 2, false), (1, 2, false), (2, 2, true)]);
+    }
+
+    #[tokio::test]
+    async fn replays_failing_job() {
+        let outcomes = replay(include_str!("../fixtures/job.jsonl"), Kind::Job, "migrate").await;
+        let messages = outcomes.iter().map(|o| o.message.as_deref()).collect::<Vec<_>>();
+        assert_eq!(messages, [
+            Some("1 pods active"),
+            Some("1 pods active, 1 failed"),
+            Some("0 pods active, 2 failed")
+        ]);
+        let failed = outcomes.iter().map(|o| o.failed.as_deref()).collect::<Vec<_>>();
+        assert_eq!(failed, [
+            None,
+            None,
+            Some("Job has reached the specified backoff limit")
+        ]);
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_session() {
+        let fixture = Replay::new(parse(include_str!("../fixtures/deployment.jsonl")).unwrap());
+        let path = std::env::temp_dir().join(format!("irae-session-{}.jsonl", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        let client = recorder.client(fixture.client("shop"), None);
+        let r = Rollout {
+            timeout: Some(Duration::from_secs(300)),
+            container: Some("app".into()),
+            on_supersede: SupersedePolicy::Abort,
+            ..rollout(Kind::Deployment, "web", client)
+        };
+        recorder.track(&r);
+        let recorded = fixture.outcomes(&r).await.unwrap();
+
+        let jsonl = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let tracked = rollouts(&jsonl).unwrap();
+        assert_eq!(tracked.len(), 1);
+        // the same requests get the same responses, without moving the clock
+        let session = parse(&jsonl).unwrap();
+        let exchanges = session.len();
+        let replay = Replay::session(session);
+        let r = replay.rollout(&tracked[0]).unwrap();
+        assert_eq!(r.workload_ref(), "shop/Deployment/web");
+        assert_eq!(r.timeout, Some(Duration::from_secs(300)));
+        assert_eq!(r.container.as_deref(), Some("app"));
+        assert_eq!(r.on_supersede, SupersedePolicy::Abort);
+        assert_eq!(r.poll, PollStrategy::Immediately(exchanges as u64));
+        let mut state = State::new(&r.infer_parameters().await.unwrap()).unwrap();
+        r.pin(&mut state).await.unwrap();
+        let mut replayed = vec![];
+        for _ in &recorded {
+            replayed.push(r.status(&state).await.unwrap());
+        }
+        assert_eq!(progress(&replayed), progress(&recorded));
+    }
+    #[test]
+    fn rejects_invalid_status() {
+        for status in [0, 99, 1000] {
+            let line = json!({
+                "time": "2026-10-09T12:00:00Z",
+                "path": "/api/v1/namespaces/shop/pods",
+                "status": status,
+                "body": {},
+            });
+            assert!(
+                matches!(parse(&line.to_string()), Err(Error::IllegalDocument)),
+                "{status}"
+            );
+        }
+    }
+}
@@ -1,19 +1,20 @@
-use crate::{version_label, Error, Kind, Result, Rollout};
+use crate::{verify::image_tag, Error, Inference, Kind, Result, Rollout};
 
 use k8s_openapi::{
     api::{
         apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet},
+        batch::v1::Job,
         core::v1::{Container, Pod, PodTemplateSpec},
     },
     apimachinery::pkg::apis::meta::v1::Time as K8sTime,
 };
 use kube::{
     api::{ListParams, LogParams},
-    core::{NamespaceResourceScope, ObjectList, Selector},
+    core::{Expression, NamespaceResourceScope, ObjectList, Selector},
     Api, Resource, ResourceExt,
 };
 use semver::Version;
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
 //use std::time::Instant;
 //use time::{ext::InstantExt, Duration};
 use time::Duration;
@@ -21,7 +22,7 @@ use time::Duration;
 
 // helpers to do kube api queries
 impl Rollout {
-    fn ns<K>(&self) -> Api<K>
+    pub(crate) fn ns<K>(&self) -> Api<K>
     where
         K: Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + DeserializeOwned,
     {
@@ -34,34 +35,25 @@ impl Rollout {
 
     /// Determine the currently leading replicaset
     ///
-    /// Use standard label app.kubernetes.io/version to determine replicaset to track
+    /// Use the version label (app.kubernetes.io/version by default) to determine replicaset to track
     /// This is flawed in cases of rollbacks (where older versions' sets may be reclaimed)
     /// ..but need a more dedicated label to target otherwise
     pub async fn get_highest_version_replicaset(&self, selector: &Selector) -> Result<Option<ReplicaSet>> {
         // NB: replicaset selectors are based on the deployment selectors with an extra template hash
-        let lp = ListParams::default().labels_from(&selector);
+        let lp = ListParams::default().labels_from(selector);
         let sets = self.ns().list(&lp).await.map_err(Error::Kube)?;
-        let mut max_ver = semver::Version::new(0, 0, 0);
-        let mut best = None;
-        for rs in sets {
-            let v = version_label(&rs)?;
-            if v > max_ver {
-                max_ver = v;
-                best = Some(rs)
-            }
-        }
-        Ok(best)
+        self.versioning.newest(sets)
     }
 
     pub async fn get_rs(&self, selector: &Selector) -> Result<Option<ReplicaSet>> {
-        let lp = ListParams::default().labels_from(&selector);
+        let lp = ListParams::default().labels_from(selector);
         let rs = self.ns().list(&lp).await.map_err(Error::Kube)?;
         assert_eq!(rs.items.len(), 1, "only one matching replicaset candidate");
         Ok(rs.items.first().cloned())
     }
 
     pub async fn get_pods(&self, selector: &Selector) -> Result<ObjectList<Pod>> {
-        let lp = ListParams::default().labels_from(&selector);
+        let lp = ListParams::default().labels_from(selector);
         let pods = self.ns().list(&lp).await.map_err(Error::Kube)?;
         Ok(pods)
     }
@@ -81,10 +73,47 @@ impl Rollout {
         Ok(sts)
     }
 
-    pub async fn get_pod_logs(&self, podname: &str) -> Result<String> {
+    pub async fn get_job(&self) -> Result<Job> {
+        let job = self.ns().get(&self.name).await.map_err(Error::Kube)?;
+        Ok(job)
+    }
+
+    /// Version of the main container in the tracked revision
+    ///
+    /// Taken from the tracked replicaset for deployments, and from its pods otherwise.
+    pub async fn tracked_version(&self, state: &State) -> Result<Option<String>> {
+        if let Kind::Deployment = self.workload {
+            if state.hash.is_none() {
+                return Ok(None); // no replicaset pinned
+            }
+            let rs = self.get_rs(&state.selector).await?;
+            return Ok(rs
+                .map(|rs| ReplicaSetSummary::new(rs, self.container.as_deref()))
+                .transpose()?
+                .map(|s| s.version));
+        }
+        let pods = self.get_pods(&state.selector).await?;
+        let version = pods
+            .into_iter()
+            .filter_map(|p| PodSummary::new(p, self.container.as_deref()).ok())
+            .find_map(|p| p.version);
+        Ok(version)
+    }
+
+    /// Fetch the last `lines` log lines from a container in a pod
+    ///
+    /// With `previous`, logs come from the last terminated instance of the container.
+    pub async fn get_pod_logs(
+        &self,
+        podname: &str,
+        container: &str,
+        previous: bool,
+        lines: i64,
+    ) -> Result<String> {
         let lp = LogParams {
-            tail_lines: Some(30),
-            container: Some(self.name.to_string()),
+            tail_lines: Some(lines),
+            container: Some(container.to_string()),
+            previous,
             ..Default::default()
         };
         let logs = self.ns::<Pod>().logs(podname, &lp).await.map_err(Error::Kube)?;
@@ -99,7 +128,7 @@ impl Rollout {
 ///
 /// Provides a single snapshot from a point in time during a rollout of how far along we are.
 /// Consumers should poll for this periodically and update states accordingly.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Outcome {
     /// How far along the rollout we are
     pub progress: u32,
@@ -112,16 +141,104 @@ pub struct Outcome {
     pub message: Option<String>,
     /// Whether rollout completed and we should stop polling
     pub ok: bool,
+    /// Why the rollout can no longer complete (a job that failed)
+    pub failed: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct State {
     /// Template hash identifying child objects (such as replicasets)
     pub hash: Option<String>,
+    /// Generation of the workload when the hash was pinned
+    pub generation: Option<i64>,
     /// Replica count to track
+    ///
+    /// Follows the autoscaler when one targets the workload.
     pub min_replicas: u32,
+    /// Name of the HorizontalPodAutoscaler scaling the workload (if any)
+    pub autoscaler: Option<String>,
     /// Moving selector to track (sometimes targets change before finishing)
+    #[serde(serialize_with = "display")]
     pub selector: Selector,
+    /// Selector for all pods of the workload (across revisions)
+    #[serde(serialize_with = "display")]
+    pub workload_selector: Selector,
+}
+
+impl State {
+    /// Initial state from inferred workload parameters (before pinning a revision)
+    pub fn new(params: &Inference) -> Result<State> {
+        let selector: Selector = params
+            .selector
+            .clone()
+            .try_into()
+            .map_err(|e| Error::KubeInvariant(format!("malformed label selector: {e}")))?;
+        Ok(State {
+            hash: None,
+            generation: None,
+            min_replicas: params.min_replicas,
+            autoscaler: None,
+            selector: selector.clone(),
+            workload_selector: selector,
+        })
+    }
+
+    /// Track a specific revision hash of the workload
+    pub fn pin_hash(&mut self, kind: &Kind, hash: String) {
+        let mut selector = self.workload_selector.clone();
+        if let Kind::Deployment = kind {
+            // pods of a replicaset carry its template hash
+            selector.extend(Expression::Equal("pod-template-hash".into(), hash.clone()));
+        }
+        self.selector = selector;
+        self.hash = Some(hash);
+    }
+}
+
+/// How a tracked rollout ended
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The tracked revision rolled out completely
+    Succeeded,
+    /// The rollout did not complete within the wait time
+    TimedOut,
+    /// A newer revision (with the contained hash) replaced the tracked one
+    Superseded(String),
+    /// The workload is paused so nothing will roll out
+    Paused,
+    /// The apply did not change anything that needed rolling out
+    Unchanged,
+    /// Ready pods run something other than the expected version (described per pod)
+    WrongVersion(Vec<String>),
+    /// The workload failed for the contained reason (a job exhausted its retries)
+    Failed(String),
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verdict::Succeeded => write!(f, "succeeded"),
+            Verdict::TimedOut => write!(f, "timed out"),
+            Verdict::Superseded(hash) => write!(f, "superseded by {hash}"),
+            Verdict::Paused => write!(f, "paused"),
+            Verdict::Unchanged => write!(f, "no change"),
+            Verdict::WrongVersion(pods) => write!(f, "wrong version: {}", pods.join(", ")),
+            Verdict::Failed(reason) => write!(f, "failed: {reason}"),
+        }
+    }
+}
+
+impl Verdict {
+    /// Whether the rollout finished successfully
+    pub fn is_success(&self) -> bool {
+        matches!(self, Verdict::Succeeded | Verdict::Unchanged)
+    }
+
+    /// Whether pods are stuck or failing, so a diagnosis can explain the verdict
+    pub fn needs_diagnosis(&self) -> bool {
+        matches!(self, Verdict::TimedOut | Verdict::Failed(_))
+    }
 }
 
 impl Rollout {
@@ -131,135 +248,348 @@ impl Rollout {
             Kind::Deployment => rollout_status_deploy(self, state).await,
             Kind::StatefulSet => rollout_status_statefulset(self, state).await,
             Kind::DaemonSet => rollout_status_daemonset(self, state).await,
+            Kind::Job => rollout_status_job(self, state).await,
         }
     }
 }
 
 async fn rollout_status_deploy(r: &Rollout, state: &State) -> Result<Outcome> {
-    // Get root data from Deployment status
     let deploy = r.get_deploy().await?;
-    let name = deploy.name_any();
-    let d = DeploySummary::try_from(deploy)?;
-    debug!("{}: {:?}", r.name, d);
-    // Wait for at least the minimum number...
-
-    let mut accurate_progress = None; // accurate progress number
-    let mut minimum = state.min_replicas; // minimum replicas we wait for
-    if state.hash.is_some() {
-        // Infer from pinned ReplicaSet status (that was latest during apply)
-        if let Some(rs) = r.get_rs(&state.selector).await? {
-            let r = ReplicaSetSummary::try_from(rs)?;
-            debug!("{name}: {r:?}");
-            accurate_progress = Some(r.ready);
-            // rs might have scaled it up during rollout
-            minimum = std::cmp::max(minimum, r.replicas.try_into().unwrap_or(0));
+    let rs = match state.hash {
+        Some(_) => r.get_rs(&state.selector).await?,
+        None => None,
+    };
+    Outcome::deployment(deploy, rs, state, r.container.as_deref())
+}
+
+async fn rollout_status_statefulset(r: &Rollout, state: &State) -> Result<Outcome> {
+    Outcome::statefulset(r.get_statefulset().await?, state)
+}
+
+async fn rollout_status_daemonset(r: &Rollout, state: &State) -> Result<Outcome> {
+    Outcome::daemonset(r.get_daemonset().await?, state)
+}
+
+async fn rollout_status_job(r: &Rollout, state: &State) -> Result<Outcome> {
+    Ok(Outcome::job(r.get_job().await?, state))
+}
+
+/// Status decisions from fetched objects
+///
+/// These make no api calls, so they can be run against recorded objects.
+impl Outcome {
+    /// Outcome of a deployment, and the replicaset pinned in the state (if any)
+    ///
+    /// The version of the replicaset is taken from the named container (or the default container).
+    pub fn deployment(
+        deploy: Deployment,
+        rs: Option<ReplicaSet>,
+        state: &State,
+        container: Option<&str>,
+    ) -> Result<Outcome> {
+        // Get root data from Deployment status
+        let name = deploy.name_any();
+        let d = DeploySummary::try_from(deploy)?;
+        debug!("{name}: {d:?}");
+        // Wait for at least the minimum number...
+
+        let mut accurate_progress = None; // accurate progress number
+        let mut minimum = state.min_replicas; // minimum replicas we wait for
+        if state.hash.is_some() {
+            // Infer from pinned ReplicaSet status (that was latest during apply)
+            if let Some(rs) = rs {
+                let r = ReplicaSetSummary::new(rs, container)?;
+                debug!("{name}: {r:?}");
+                accurate_progress = Some(r.ready);
+                // rs might have scaled it up during rollout
+                minimum = std::cmp::max(minimum, r.replicas.try_into().unwrap_or(0));
+            }
         }
+
+        // Decide whether to stop polling - did the upgrade pass?
+        let ok = if let Some(acc) = accurate_progress {
+            // Replicaset is scaled to our minimum, and all ready
+            // NB: k8s >= 1.15 we use `d.new_replicas_available`
+            // as a better required check
+            acc == i32::try_from(minimum)
+                .expect("min number of replicas should have been within bounds of a i32")
+        // NB: This last && enforces the progress downscaling at the end of fn
+        } else {
+            // FALLBACK (never seems to really happen): count from deployment only
+            // Need to at least have as many ready as expected
+            // ...it needs to have been scaled to the correct minimum
+            // ...and, either we have the explicit progress done, or all unavailables are gone
+            // The last condition (which increases waiting time) is necessary because:
+            // deployment summary aggregates up the total number of ready pods
+            // so we won't really know we're done or not unless we got the go-ahead
+            // (i.e. d.new_replicas_available in k8s >= 1.15),
+            // or all the unavailable pods have been killed (indicating total completeness)
+            d.ready == d.replicas
+                && d.ready
+                    >= minimum
+                        .try_into()
+                        .expect("min number of replicas should have been within bounds of a i32")
+                && (d.new_replicas_available || d.unavailable <= 0)
+        };
+
+        //  What to tell our progress bar:
+        let progress: i32 = match accurate_progress {
+            // 99% case: the number from our accurately matched replicaset:
+            Some(p) => p,
+
+            // Otherwise estimate based on deployment.status data
+            // Slightly weird data because of replicasets worth of data is here..
+            // There might be more than one deployment in progress, all of which surge..
+            None => std::cmp::max(0, d.ready - d.unavailable),
+        };
+        Ok(Outcome {
+            progress: progress
+                .try_into()
+                .map_err(|_e| Error::KubeInvariant("progress >= 0".to_string()))?,
+            expected: minimum,
+            message: d.message,
+            ok,
+            failed: None,
+        })
     }
 
-    // Decide whether to stop polling - did the upgrade pass?
-    let ok = if let Some(acc) = accurate_progress {
-        // Replicaset is scaled to our minimum, and all ready
-        // NB: k8s >= 1.15 we use `d.new_replicas_available`
-        // as a better required check
-        acc == i32::try_from(minimum).expect("min number of replicas should have been within bounds of a i32")
-    // NB: This last && enforces the progress downscaling at the end of fn
-    } else {
-        // FALLBACK (never seems to really happen): count from deployment only
-        // Need to at least have as many ready as expected
-        // ...it needs to have been scaled to the correct minimum
-        // ...and, either we have the explicit progress done, or all unavailables are gone
-        // The last condition (which increases waiting time) is necessary because:
-        // deployment summary aggregates up the total number of ready pods
-        // so we won't really know we're done or not unless we got the go-ahead
-        // (i.e. d.new_replicas_available in k8s >= 1.15),
-        // or all the unavailable pods have been killed (indicating total completeness)
-        d.ready == d.replicas
-            && d.ready
-                >= minimum
-                    .try_into()
-                    .expect("min number of replicas should have been within bounds of a i32")
-            && (d.new_replicas_available || d.unavailable <= 0)
-    };
+    /// Outcome of a statefulset
+    pub fn statefulset(sts: StatefulSet, state: &State) -> Result<Outcome> {
+        let s = StatefulSummary::try_from(sts)?;
+        let minimum = state.min_replicas;
 
-    //  What to tell our progress bar:
-    let progress: i32 = match accurate_progress {
-        // 99% case: the number from our accurately matched replicaset:
-        Some(p) => p,
+        let ok = s.updated_replicas
+            >= i32::try_from(minimum)
+                .expect("min number of replicas should have been within bounds of a i32")
+            && s.updated_replicas == s.ready
+            && s.update_revision == state.hash;
+        let message = if ok {
+            None
+        } else {
+            Some("Statefulset update in progress".to_string())
+        };
 
-        // Otherwise estimate based on deployment.status data
-        // Slightly weird data because of replicasets worth of data is here..
-        // There might be more than one deployment in progress, all of which surge..
-        None => std::cmp::max(0, d.ready - d.unavailable),
-    };
-    Ok(Outcome {
-        progress: progress
-            .try_into()
-            .map_err(|_e| Error::KubeInvariant("progress >= 0".to_string()))?,
-        expected: minimum,
-        message: d.message,
-        ok,
-    })
-}
+        // NB: Progress is slightly optimistic because updated_replicas increment
+        // as soon as the old replica is replaced, not when it's ready.
+        // (we can't use ready_replicas because that counts the sum of old + new)
+        // But this is OK. If it gets to 3/3 then at least 2 rolled out successfully,
+        // and the third was started. The only other way of getting around that
+        // would be tracking the pods with the new hash directly..
 
-async fn rollout_status_statefulset(r: &Rollout, state: &State) -> Result<Outcome> {
-    let ss = r.get_statefulset().await?;
-    let s = StatefulSummary::try_from(ss)?;
-    let minimum = state.min_replicas;
-
-    let ok = s.updated_replicas
-        >= i32::try_from(minimum).expect("min number of replicas should have been within bounds of a i32")
-        && s.updated_replicas == s.ready
-        && s.update_revision == state.hash;
-    let message = if ok {
-        None
-    } else {
-        Some("Statefulset update in progress".to_string())
-    };
+        // Note that while progressbar is optimistic, it's not marked as ok (done)
+        // until the new revision is changed over (when sts controller thinks it's done)
+        // So this is a progressbar only inconsistency.
+        Ok(Outcome {
+            progress: std::cmp::max(0, s.updated_replicas)
+                .try_into()
+                .expect("sts.updated_replicas >= 0"),
+            expected: minimum,
+            message,
+            ok,
+            failed: None,
+        })
+    }
 
-    // NB: Progress is slightly optimistic because updated_replicas increment
-    // as soon as the old replica is replaced, not when it's ready.
-    // (we can't use ready_replicas because that counts the sum of old + new)
-    // But this is OK. If it gets to 3/3 then at least 2 rolled out successfully,
-    // and the third was started. The only other way of getting around that
-    // would be tracking the pods with the new hash directly..
-
-    // Note that while progressbar is optimistic, it's not marked as ok (done)
-    // until the new revision is changed over (when sts controller thinks it's done)
-    // So this is a progressbar only inconsistency.
-    Ok(Outcome {
-        progress: std::cmp::max(0, s.updated_replicas)
-            .try_into()
-            .expect("sts.updated_replicas >= 0"),
-        expected: minimum,
-        message,
-        ok,
-    })
+    /// Outcome of a daemonset (experimental)
+    pub fn daemonset(ds: DaemonSet, state: &State) -> Result<Outcome> {
+        let s = DaemonSummary::try_from(ds)?;
+        let minimum = state.min_replicas;
+
+        let ok = s.desired
+            >= i32::try_from(minimum)
+                .expect("min number of replicas should have been within bounds of a i32")
+            && Some(s.desired) == s.updated;
+        let message = if ok {
+            None
+        } else {
+            Some("Daemonset update in progress".to_string())
+        };
+        Ok(Outcome {
+            progress: std::cmp::max(0, s.updated.unwrap_or(s.ready))
+                .try_into()
+                .expect("sts.updated_replicas >= 0"),
+            expected: minimum,
+            message,
+            ok,
+            failed: None,
+        })
+    }
+
+    /// Outcome of a job
+    pub fn job(job: Job, state: &State) -> Outcome {
+        let status = job.status.unwrap_or_default();
+        let conditions = status.conditions.unwrap_or_default();
+        let condition = |type_: &str| conditions.iter().find(|c| c.type_ == type_ && c.status == "True");
+        let failed = condition("Failed").map(|c| {
+            let reason = c.message.clone().or(c.reason.clone());
+            reason.unwrap_or_else(|| "job failed".to_string())
+        });
+        let ok = condition("Complete").is_some();
+        let message = match (status.active.unwrap_or(0), status.failed.unwrap_or(0)) {
+            _ if ok => None,
+            (active, 0) => Some(format!("{active} pods active")),
+            (active, failures) => Some(format!("{active} pods active, {failures} failed")),
+        };
+        Outcome {
+            progress: std::cmp::max(0, status.succeeded.unwrap_or(0))
+                .try_into()
+                .expect("job.succeeded >= 0"),
+            expected: state.min_replicas,
+            message,
+            ok,
+            failed,
+        }
+    }
 }
 
-// daemonset experimental
-async fn rollout_status_daemonset(r: &Rollout, state: &State) -> Result<Outcome> {
-    let ds = r.get_daemonset().await?;
-    let s = DaemonSummary::try_from(ds)?;
-    let minimum = state.min_replicas;
-
-    let ok = s.desired
-        >= i32::try_from(minimum).expect("min number of replicas should have been within bounds of a i32")
-        && Some(s.desired) == s.updated;
-    let message = if ok {
-        None
-    } else {
-        Some("Daemonset update in progress".to_string())
-    };
-    Ok(Outcome {
-        progress: std::cmp::max(0, s.updated.unwrap_or(s.ready))
-            .try_into()
-            .expect("sts.updated_replicas >= 0"),
-        expected: minimum,
-        message,
-        ok,
-    })
+// ----------------------------------------------------------------------------
+// revision pinning
+
+impl Rollout {
+    /// Pin the state to the revision we are following
+    ///
+    /// This is not always sound right after an apply (multiple upgrades may clash with each other),
+    /// so `superseded` should be checked while tracking.
+    pub async fn pin(&self, state: &mut State) -> Result<()> {
+        state.generation = self.pin_generation().await?;
+        match self.workload {
+            Kind::Deployment => {
+                // Attempt to find an owning RS hash to track
+                if let Some(rs) = self
+                    .get_highest_version_replicaset(&state.workload_selector)
+                    .await?
+                {
+                    if let Some(h) = rs.labels().get("pod-template-hash") {
+                        debug!("Tracking replicaset {}", h);
+                        state.pin_hash(&self.workload, h.clone());
+                    }
+                }
+            }
+            Kind::StatefulSet => {
+                // Attempt to find an owning revision hash to track
+                let sts = self.get_statefulset().await?;
+                let summary = StatefulSummary::try_from(sts)?;
+                if let Some(ur) = summary.update_revision {
+                    debug!("Tracking statefulset {:?} for {}", ur, self.name);
+                    state.pin_hash(&self.workload, ur);
+                }
+            }
+            Kind::DaemonSet | Kind::Job => {} // tracked through their status only
+        }
+        Ok(())
+    }
+
+    /// Check whether a newer revision has replaced the one pinned in the state
+    ///
+    /// Returns the hash of the newer revision when the workload has moved on.
+    /// Changes that keep the revision (e.g. scaling) move the pinned generation along,
+    /// so the revision is not looked up again on every poll.
+    /// Nothing is decided until the controller has observed the new generation,
+    /// since the newer revision does not exist before that.
+    pub async fn superseded(&self, state: &mut State) -> Result<Option<String>> {
+        let Some(pinned) = state.hash.clone() else {
+            return Ok(None);
+        };
+        let (generation, observed) = self.generations().await?;
+        if generation == state.generation {
+            return Ok(None); // spec unchanged since we pinned
+        }
+        if observed < generation {
+            return Ok(None); // checked again on the next poll
+        }
+        let latest = self.latest_hash(state).await?;
+        if latest.as_ref().is_none_or(|h| *h == pinned) {
+            state.generation = generation;
+            return Ok(None);
+        }
+        Ok(latest)
+    }
+
+    /// Hash of the newest revision of the workload (if it records one)
+    ///
+    /// Deployments use the replicaset of their current revision annotation, which stays correct after rollbacks.
+    async fn latest_hash(&self, state: &State) -> Result<Option<String>> {
+        let latest = match self.workload {
+            Kind::Deployment => {
+                let deploy = self.get_deploy().await?;
+                let Some(revision) = deploy.annotations().get(REVISION_ANNOTATION) else {
+                    return Ok(None);
+                };
+                let lp = ListParams::default().labels_from(&state.workload_selector);
+                let sets = self.ns::<ReplicaSet>().list(&lp).await.map_err(Error::Kube)?;
+                sets.into_iter()
+                    .find(|rs| rs.annotations().get(REVISION_ANNOTATION) == Some(revision))
+                    .and_then(|rs| rs.labels().get("pod-template-hash").cloned())
+            }
+            Kind::StatefulSet => StatefulSummary::try_from(self.get_statefulset().await?)?.update_revision,
+            Kind::DaemonSet | Kind::Job => None,
+        };
+        Ok(latest)
+    }
+
+    /// Infer the state of the newest revision without waiting for anything
+    ///
+    /// Useful for inspecting a rollout that was started elsewhere.
+    pub async fn current_state(&self) -> Result<State> {
+        let params = self.infer_parameters().await?;
+        let mut state = State::new(&params)?;
+        state.generation = self.pin_generation().await?;
+        match self.latest_hash(&state).await? {
+            Some(hash) => state.pin_hash(&self.workload, hash),
+            None => self.pin(&mut state).await?,
+        }
+        Ok(state)
+    }
+
+    /// Generation of the workload and the generation its controller has observed
+    ///
+    /// Jobs do not report an observed generation, and are taken as observed.
+    async fn generations(&self) -> Result<(Option<i64>, Option<i64>)> {
+        let generations = match self.workload {
+            Kind::Deployment => {
+                let d = self.get_deploy().await?;
+                (
+                    d.metadata.generation,
+                    d.status.and_then(|s| s.observed_generation),
+                )
+            }
+            Kind::StatefulSet => {
+                let sts = self.get_statefulset().await?;
+                (
+                    sts.metadata.generation,
+                    sts.status.and_then(|s| s.observed_generation),
+                )
+            }
+            Kind::DaemonSet => {
+                let ds = self.get_daemonset().await?;
+                (
+                    ds.metadata.generation,
+                    ds.status.and_then(|s| s.observed_generation),
+                )
+            }
+            Kind::Job => {
+                let generation = self.get_job().await?.metadata.generation;
+                (generation, generation)
+            }
+        };
+        Ok(generations)
+    }
+
+    /// Current generation of the workload (recorded in the state when pinning)
+    pub async fn pin_generation(&self) -> Result<Option<i64>> {
+        let generation = match self.workload {
+            Kind::Deployment => self.get_deploy().await?.metadata.generation,
+            Kind::StatefulSet => self.get_statefulset().await?.metadata.generation,
+            Kind::DaemonSet => self.get_daemonset().await?.metadata.generation,
+            Kind::Job => self.get_job().await?.metadata.generation,
+        };
+        Ok(generation)
+    }
 }
 
+/// Revision number annotated on deployments and their replicasets
+pub(crate) const REVISION_ANNOTATION: &str = "deployment.kubernetes.io/revision";
+
 // ----------------------------------------------------------------------------
 // misc formatting helpers
 
@@ -272,7 +602,8 @@ fn short_ver(ver: &str) -> String {
     }
 }
 
-fn format_duration(dur: Duration) -> String {
+/// Coarse human readable duration (like kubectl ages)
+pub(crate) fn format_duration(dur: Duration) -> String {
     let days = dur.whole_days();
     let hours = dur.whole_hours();
     let mins = dur.whole_minutes();
@@ -285,31 +616,56 @@ fn format_duration(dur: Duration) -> String {
     }
 }
 
+/// Time since a creation timestamp
+pub(crate) fn age(created: Option<&K8sTime>) -> Duration {
+    let Some(ts) = created else {
+        return Duration::ZERO;
+    };
+    let secs = chrono::Utc::now().signed_duration_since(ts.0).num_seconds();
+    Duration::seconds(secs.max(0))
+}
+
+fn display<T: std::fmt::Display, S: serde::Serializer>(v: &T, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.collect_str(v)
+}
+
+pub(crate) fn seconds<S: serde::Serializer>(dur: &Duration, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_i64(dur.whole_seconds())
+}
+
 // ----------------------------------------------------------------------------
 // misc version extraction helpers
 
-fn extract_container<'a>(containers: &'a [Container], request: Option<&'a String>) -> Option<&'a Container> {
+fn extract_container<'a>(containers: &'a [Container], request: Option<&str>) -> Option<&'a Container> {
     let mut app_container = None;
     if let Some(specified) = request {
-        app_container = containers.iter().find(|p| p.name == *specified);
+        app_container = containers.iter().find(|p| p.name == specified);
     }
-    let main_container = if let Some(appc) = app_container {
-        appc
-    } else {
-        &containers[0]
-    };
-    Some(main_container)
+    app_container.or(containers.first())
 }
 
-fn default_container(pod: &Pod) -> Option<&Container> {
+/// The main container of a pod
+///
+/// An explicitly requested container wins over the kubectl default-container annotation.
+pub(crate) fn main_container<'a>(pod: &'a Pod, container: Option<&str>) -> Option<&'a Container> {
     let annotations = pod.annotations();
     let default_container = annotations.get("kubectl.kubernetes.io/default-container");
-    if let Some(spec) = &pod.spec {
-        extract_container(&spec.containers, default_container)
-    } else {
-        None
-    }
+    let spec = pod.spec.as_ref()?;
+    extract_container(
+        &spec.containers,
+        container.or(default_container.map(String::as_str)),
+    )
+}
+
+/// Version of the main container in a pod template
+pub(crate) fn template_version(tpl: &PodTemplateSpec, container: Option<&str>) -> Option<String> {
+    let podspec = tpl.spec.as_ref()?;
+    let default_container = find_default_in_rs(tpl);
+    let main = extract_container(&podspec.containers, container.or(default_container.as_deref()))?;
+    let tag = image_tag(main.image.as_deref()?)?;
+    Some(short_ver(&tag))
 }
+
 fn find_default_in_rs(rs: &PodTemplateSpec) -> Option<String> {
     let meta = rs.metadata.as_ref()?;
     let annotations = meta.annotations.clone().unwrap_or_default();
@@ -319,14 +675,15 @@ fn find_default_in_rs(rs: &PodTemplateSpec) -> Option<String> {
 }
 
 // ----------------------------------------------------------------------------
-// pod inspection - currently unused
+// pod inspection
 
 /// A summary of a Pod's status
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PodSummary {
     /// Name of the pod inspected
     pub name: String,
     /// Age since the pod's creationTimestamp
+    #[serde(serialize_with = "seconds")]
     pub age: Duration,
     /// Phase from the status object
     pub phase: Option<String>,
@@ -338,6 +695,15 @@ pub struct PodSummary {
     pub restarts: i32,
     /// Version tag seen in image of main container
     pub version: Option<String>,
+    /// Why the pod is not ready (waiting reason of a container, or scheduling failure)
+    pub reason: Option<String>,
+}
+
+impl PodSummary {
+    /// Whether all containers of the pod are ready
+    pub fn is_ready(&self) -> bool {
+        self.containers > 0 && self.running == self.containers as i32
+    }
 }
 
 impl TryFrom<Pod> for PodSummary {
@@ -345,38 +711,46 @@ impl TryFrom<Pod> for PodSummary {
 
     /// Helper to convert the openapi Pod to the useful info
     fn try_from(pod: Pod) -> Result<PodSummary> {
+        PodSummary::new(pod, None)
+    }
+}
+
+impl PodSummary {
+    /// Summarise a pod, taking its version from the given main container (if any)
+    pub fn new(pod: Pod, container: Option<&str>) -> Result<PodSummary> {
         let name = pod.name_any();
-        let ts = pod
-            .creation_timestamp()
-            .unwrap_or(K8sTime(chrono::DateTime::<chrono::Utc>::MIN_UTC))
-            .0;
-        // TODO: we can't range error here can we?
-        let age_std = chrono::Utc::now().signed_duration_since(ts).to_std().unwrap();
-        let age = time::Duration::try_from(age_std).unwrap();
+        // saturates at zero when the pod was created ahead of the local clock
+        let age = age(pod.creation_timestamp().as_ref());
 
         let mut running = 0;
         let mut containers = 0;
         let mut restarts = 0;
         let mut phase = None;
+        let mut reason = None;
         if let Some(status) = &pod.status {
             phase = status.phase.clone();
             for s in status.container_statuses.clone().unwrap_or_default() {
                 running += if s.ready { 1 } else { 0 };
                 containers += 1;
                 restarts = std::cmp::max(restarts, s.restart_count);
+                if !s.ready && reason.is_none() {
+                    reason = s.state.and_then(|st| st.waiting).and_then(|w| w.reason);
+                }
+            }
+            if reason.is_none() {
+                // pods that never got scheduled have no container statuses
+                let conds = status.conditions.clone().unwrap_or_default();
+                if let Some(c) = conds
+                    .iter()
+                    .find(|c| c.type_ == "PodScheduled" && c.status == "False")
+                {
+                    reason = c.reason.clone();
+                }
             }
         }
-        let mut version = None;
-        if let Some(main_container) = default_container(&pod) {
-            version = Some(short_ver(
-                main_container
-                    .image
-                    .as_ref()
-                    .unwrap()
-                    .split(':')
-                    .collect::<Vec<_>>()[1],
-            ))
-        };
+        let version = main_container(&pod, container)
+            .and_then(|c| image_tag(c.image.as_deref()?))
+            .map(|tag| short_ver(&tag));
         Ok(PodSummary {
             name,
             age,
@@ -385,6 +759,7 @@ impl TryFrom<Pod> for PodSummary {
             running,
             containers,
             restarts,
+            reason,
         })
     }
 }
@@ -393,7 +768,7 @@ impl TryFrom<Pod> for PodSummary {
 // replicaset inspection
 
 /// A summary of a ReplicaSet's status
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ReplicaSetSummary {
     pub hash: String,
     pub version: String,
@@ -406,6 +781,13 @@ impl TryFrom<ReplicaSet> for ReplicaSetSummary {
 
     /// Helper to convert the openapi ReplicaSet to the useful info
     fn try_from(rs: ReplicaSet) -> Result<ReplicaSetSummary> {
+        ReplicaSetSummary::new(rs, None)
+    }
+}
+
+impl ReplicaSetSummary {
+    /// Summarise a replicaset, taking its version from the given main container (if any)
+    pub fn new(rs: ReplicaSet, container: Option<&str>) -> Result<ReplicaSetSummary> {
         let Some(status) = rs.status.clone() else {
             Err(Error::KubeInvariant(
                 "Missing replicaset status object".to_string(),
@@ -414,19 +796,11 @@ impl TryFrom<ReplicaSet> for ReplicaSetSummary {
         let name = rs.name_any();
         let replicas = status.replicas;
         let ready = status.ready_replicas.unwrap_or(0);
-        let mut ver = None;
-        if let Some(spec) = &rs.spec {
-            if let Some(tpl) = &spec.template {
-                if let Some(podspec) = &tpl.spec {
-                    let default_container = find_default_in_rs(tpl);
-                    if let Some(main) = extract_container(&podspec.containers, default_container.as_ref()) {
-                        let image = main.image.clone().unwrap_or(":".to_string());
-                        let tag = image.split(':').collect::<Vec<_>>()[1];
-                        ver = Some(short_ver(tag));
-                    }
-                }
-            }
-        };
+        let ver = rs
+            .spec
+            .as_ref()
+            .and_then(|s| s.template.as_ref())
+            .and_then(|tpl| template_version(tpl, container));
         let version = ver.unwrap_or_else(|| "unknown version".to_string());
         let hash = match rs.labels().get("pod-template-hash") {
             Some(h) => h.to_owned(),
@@ -548,3 +922,113 @@ impl TryFrom<DaemonSet> for DaemonSummary {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    #[test]
+    fn template_version_handles_untagged_images() {
+        let tpl = |image: &str| -> PodTemplateSpec {
+            testing::object(json!({
+                "spec": { "containers": [{ "name": "app", "image": image }] }
+            }))
+        };
+        assert_eq!(
+            template_version(&tpl("shop/app:1.4.2"), None).as_deref(),
+            Some("1.4.2")
+        );
+        assert_eq!(
+            template_version(&tpl("registry:5000/app:2.0"), None).as_deref(),
+            Some("2.0")
+        );
+        assert_eq!(template_version(&tpl("registry:5000/app"), None), None);
+        assert_eq!(template_version(&tpl("nginx"), None), None);
+        assert_eq!(template_version(&tpl("app@sha256:abc"), None), None);
+    }
+
+    #[test]
+    fn job_outcomes() {
+        let state = testing::state(1);
+        let job = |status: serde_json::Value| testing::job(json!({ "status": status }));
+
+        let suspended = Outcome::job(job(json!({})), &state);
+        assert!(!suspended.ok && suspended.failed.is_none());
+        assert_eq!(suspended.message.as_deref(), Some("0 pods active"));
+
+        let complete = job(json!({
+            "succeeded": 1,
+            "conditions": [{ "type": "Complete", "status": "True" }]
+        }));
+        let complete = Outcome::job(complete, &state);
+        assert!(complete.ok && complete.failed.is_none() && complete.message.is_none());
+        assert_eq!((complete.progress, complete.expected), (1, 1));
+
+        let failed = job(json!({
+            "failed": 3,
+            "conditions": [{
+                "type": "Failed",
+                "status": "True",
+                "reason": "BackoffLimitExceeded",
+                "message": "Job has reached the specified backoff limit"
+            }]
+        }));
+        let failed = Outcome::job(failed, &state);
+        assert!(!failed.ok);
+        assert_eq!(
+            failed.failed.as_deref(),
+            Some("Job has reached the specified backoff limit")
+        );
+        assert_eq!(failed.message.as_deref(), Some("0 pods active, 3 failed"));
+    }
+
+    #[test]
+    fn diagnoses_stuck_and_failed_rollouts() {
+        assert!(Verdict::TimedOut.needs_diagnosis());
+        assert!(Verdict::Failed("BackoffLimitExceeded".into()).needs_diagnosis());
+        assert!(!Verdict::Succeeded.needs_diagnosis());
+        assert!(!Verdict::Superseded("7c9d8f".into()).needs_diagnosis());
+        assert!(!Verdict::WrongVersion(vec![]).needs_diagnosis());
+    }
+
+    #[test]
+    fn pod_ages_survive_clock_skew() {
+        let created = |offset: chrono::Duration| {
+            let ts = (chrono::Utc::now() + offset).to_rfc3339();
+            testing::pod(json!({ "metadata": { "creationTimestamp": ts } }))
+        };
+        let ahead = PodSummary::new(created(chrono::Duration::minutes(5)), None).unwrap();
+        assert_eq!(ahead.age, Duration::ZERO);
+        let started = PodSummary::new(created(chrono::Duration::minutes(-5)), None).unwrap();
+        assert!(started.age >= Duration::minutes(5), "{}", started.age);
+        let unknown = PodSummary::new(testing::pod(json!({})), None).unwrap();
+        assert_eq!(unknown.age, Duration::ZERO);
+    }
+
+    #[cfg(feature = "replay")]
+    #[tokio::test]
+    async fn waits_for_the_controller_before_superseding() {
+        use crate::replay::{Exchange, Replay};
+        // reapplied, but the controller has not made a revision of generation 3 yet
+        let deploy = testing::deployment(json!({
+            "metadata": { "generation": 3 },
+            "status": { "observedGeneration": 2 }
+        }));
+        let replay = Replay::new(vec![Exchange {
+            time: time::OffsetDateTime::UNIX_EPOCH,
+            method: "GET".into(),
+            path: "/apis/apps/v1/namespaces/shop/deployments/web".into(),
+            status: 200,
+            body: serde_json::to_value(&deploy).unwrap(),
+            context: None,
+        }]);
+        let r = Rollout::from_resource(replay.client("shop"), &deploy).unwrap();
+        let mut state = testing::state(2);
+        state.pin_hash(&Kind::Deployment, "7c9d8f".into());
+        state.generation = Some(2);
+        assert_eq!(r.superseded(&mut state).await.unwrap(), None);
+        assert_eq!(state.generation, Some(2), "generation 3 is checked again later");
+    }
+}
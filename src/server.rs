@@ -0,0 +1,127 @@
+//! http api for rollouts in a `watch::Store`
+use crate::{
+    watch::{Record, Store, WorkloadRef},
+    Error, Kind, Result,
+};
+
+use axum::{
+    extract::{Path, State as Shared},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Json, Router,
+};
+use futures::Stream;
+use serde::Serialize;
+use std::convert::Infallible;
+use tokio::{net::TcpListener, sync::broadcast::error::RecvError};
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// A workload together with its latest rollout record
+#[derive(Serialize)]
+pub struct Entry {
+    pub workload: WorkloadRef,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+impl From<(WorkloadRef, Record)> for Entry {
+    fn from((workload, record): (WorkloadRef, Record)) -> Self {
+        Entry { workload, record }
+    }
+}
+
+/// Routes for the rollout api
+///
+/// - `GET /rollouts` all active and recent rollouts
+/// - `GET /rollouts/active` rollouts still being tracked
+/// - `GET /rollouts/{namespace}/{kind}/{name}` the latest rollout of a workload
+/// - `GET /events` server-sent events for every progress update
+pub fn router(store: Store) -> Router {
+    Router::new()
+        .route("/rollouts", get(list))
+        .route("/rollouts/active", get(active))
+        .route("/rollouts/{namespace}/{kind}/{name}", get(workload))
+        .route("/events", get(events))
+        .with_state(store)
+}
+
+/// Serve the rollout api until the process exits
+///
+/// Takes a bound listener so callers see bind errors before serving in the background.
+pub async fn serve(store: Store, listener: TcpListener) -> Result<()> {
+    if let Ok(addr) = listener.local_addr() {
+        info!("Serving rollout api on {addr}");
+    }
+    axum::serve(listener, router(store)).await.map_err(Error::Io)
+}
+
+async fn list(Shared(store): Shared<Store>) -> Json<Vec<Entry>> {
+    Json(store.list().into_iter().map(Entry::from).collect())
+}
+
+async fn active(Shared(store): Shared<Store>) -> Json<Vec<Entry>> {
+    let active = store.list().into_iter().filter(|(_, rec)| rec.is_active());
+    Json(active.map(Entry::from).collect())
+}
+
+async fn workload(
+    Shared(store): Shared<Store>,
+    Path((namespace, kind, name)): Path<(String, String, String)>,
+) -> std::result::Result<Json<Entry>, StatusCode> {
+    let kind: Kind = kind.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let key = WorkloadRef {
+        namespace,
+        kind,
+        name,
+    };
+    let record = store.get(&key).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(Entry::from((key, record))))
+}
+
+async fn events(
+    Shared(store): Shared<Store>,
+) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>> {
+    let stream = futures::stream::unfold(store.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(update) => {
+                    let data = serde_json::to_string(&Entry::from(update)).expect("serializable entry");
+                    return Some((Ok(Event::default().event("update").data(data)), rx));
+                }
+                Err(RecvError::Lagged(n)) => warn!("event stream lagged by {n} updates"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn unknown_workloads_are_not_found() {
+        let app = router(Store::default());
+        let req = |uri: &str| axum::http::Request::get(uri).body(Body::empty()).unwrap();
+
+        let res = app.clone().oneshot(req("/rollouts")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"[]");
+
+        let res = app
+            .clone()
+            .oneshot(req("/rollouts/default/deploy/checkout"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = app.oneshot(req("/rollouts/default/pod/checkout")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
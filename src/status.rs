@@ -0,0 +1,158 @@
+//! one-shot snapshots of workload rollout status
+use crate::{
+    rollout::{age, format_duration, seconds, template_version, ReplicaSetSummary, REVISION_ANNOTATION},
+    watch::{RolloutStatus, WorkloadRef},
+    Error, Kind, Result, Rollout,
+};
+
+use k8s_openapi::api::apps::v1::{ControllerRevision, ReplicaSet};
+use kube::{api::ListParams, core::Selector, Resource, ResourceExt};
+use serde::Serialize;
+use time::Duration;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Rollout status of a workload at a point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub workload: WorkloadRef,
+    /// Revision serving most of the available pods
+    pub current_revision: Option<String>,
+    /// Revision being rolled out (the latest)
+    pub update_revision: Option<String>,
+    /// Version of the main container in the update revision
+    pub version: Option<String>,
+    pub desired: i32,
+    pub ready: i32,
+    pub updated: i32,
+    pub available: i32,
+    /// Time since the workload was created
+    #[serde(serialize_with = "seconds")]
+    pub age: Duration,
+    /// Whether a rollout is in progress
+    pub rolling: bool,
+}
+
+impl Snapshot {
+    /// Age in a coarse human readable form
+    pub fn age(&self) -> String {
+        format_duration(self.age)
+    }
+}
+
+impl Rollout {
+    /// Take a snapshot of the rollout status of the workload without waiting
+    pub async fn snapshot(&self) -> Result<Snapshot> {
+        let workload = WorkloadRef {
+            namespace: self.namespace_or_default(),
+            kind: self.workload.clone(),
+            name: self.name.clone(),
+        };
+        let snapshot = match self.workload {
+            Kind::Deployment => {
+                let d = self.get_deploy().await?;
+                let (spec, status) = (d.spec.clone().unwrap_or_default(), d.status.clone());
+                let status = status.unwrap_or_default();
+                let selector = Selector::try_from(spec.selector)
+                    .map_err(|e| Error::KubeInvariant(format!("malformed label selector: {e}")))?;
+                let lp = ListParams::default().labels_from(&selector);
+                let sets = self.ns::<ReplicaSet>().list(&lp).await.map_err(Error::Kube)?;
+                let revision = d.annotations().get(REVISION_ANNOTATION);
+                let update = sets
+                    .iter()
+                    .find(|rs| revision.is_some() && rs.annotations().get(REVISION_ANNOTATION) == revision);
+                let current = sets
+                    .iter()
+                    .filter(|rs| rs.status.as_ref().and_then(|s| s.available_replicas).unwrap_or(0) > 0)
+                    .max_by_key(|rs| rs.status.as_ref().and_then(|s| s.available_replicas));
+                let hash = |rs: &ReplicaSet| rs.labels().get("pod-template-hash").cloned();
+                Snapshot {
+                    current_revision: current.and_then(hash),
+                    update_revision: update.and_then(hash),
+                    version: update
+                        .cloned()
+                        .and_then(|rs| ReplicaSetSummary::new(rs, self.container.as_deref()).ok())
+                        .map(|s| s.version),
+                    desired: spec.replicas.unwrap_or(1),
+                    ready: status.ready_replicas.unwrap_or(0),
+                    updated: status.updated_replicas.unwrap_or(0),
+                    available: status.available_replicas.unwrap_or(0),
+                    age: age(d.meta().creation_timestamp.as_ref()),
+                    rolling: d.is_rolling(),
+                    workload,
+                }
+            }
+            Kind::StatefulSet => {
+                let sts = self.get_statefulset().await?;
+                let spec = sts.spec.clone().unwrap_or_default();
+                let status = sts.status.clone().unwrap_or_default();
+                Snapshot {
+                    current_revision: status.current_revision,
+                    update_revision: status.update_revision,
+                    version: template_version(&spec.template, self.container.as_deref()),
+                    desired: spec.replicas.unwrap_or(1),
+                    ready: status.ready_replicas.unwrap_or(0),
+                    updated: status.updated_replicas.unwrap_or(0),
+                    available: status.available_replicas.unwrap_or(0),
+                    age: age(sts.meta().creation_timestamp.as_ref()),
+                    rolling: sts.is_rolling(),
+                    workload,
+                }
+            }
+            Kind::DaemonSet => {
+                let ds = self.get_daemonset().await?;
+                let spec = ds.spec.clone().unwrap_or_default();
+                let status = ds.status.clone().unwrap_or_default();
+                let selector = Selector::try_from(spec.selector.clone())
+                    .map_err(|e| Error::KubeInvariant(format!("malformed label selector: {e}")))?;
+                let lp = ListParams::default().labels_from(&selector);
+                let revisions = self
+                    .ns::<ControllerRevision>()
+                    .list(&lp)
+                    .await
+                    .map_err(Error::Kube)?;
+                let latest = revisions
+                    .iter()
+                    .max_by_key(|cr| cr.revision)
+                    .map(|cr| cr.name_any());
+                let rolling = ds.is_rolling();
+                Snapshot {
+                    // daemonsets do not record which revision is current mid-rollout
+                    current_revision: if rolling { None } else { latest.clone() },
+                    update_revision: latest,
+                    version: template_version(&spec.template, self.container.as_deref()),
+                    desired: status.desired_number_scheduled,
+                    ready: status.number_ready,
+                    updated: status.updated_number_scheduled.unwrap_or(0),
+                    available: status.number_available.unwrap_or(0),
+                    age: age(ds.meta().creation_timestamp.as_ref()),
+                    rolling,
+                    workload,
+                }
+            }
+            Kind::Job => {
+                let job = self.get_job().await?;
+                let spec = job.spec.clone().unwrap_or_default();
+                let status = job.status.clone().unwrap_or_default();
+                let conditions = status.conditions.unwrap_or_default();
+                let finished = conditions
+                    .iter()
+                    .any(|c| matches!(c.type_.as_str(), "Complete" | "Failed") && c.status == "True");
+                let succeeded = status.succeeded.unwrap_or(0);
+                Snapshot {
+                    // jobs are not revisioned
+                    current_revision: None,
+                    update_revision: None,
+                    version: template_version(&spec.template, self.container.as_deref()),
+                    desired: spec.completions.unwrap_or(1),
+                    ready: status.ready.unwrap_or(0),
+                    updated: succeeded,
+                    available: succeeded,
+                    age: age(job.meta().creation_timestamp.as_ref()),
+                    rolling: !finished,
+                    workload,
+                }
+            }
+        };
+        Ok(snapshot)
+    }
+}
@@ -1,120 +1,415 @@
-use crate::{estimate, Error, Kind, Result, Rollout, State, StatefulSummary};
-use indicatif::{ProgressBar, ProgressStyle};
-use kube::{
-    core::{Expression, Selector},
-    ResourceExt,
+use crate::{Diagnosis, Kind, Observer, Outcome, Result, Revision, Rollout, Snapshot, State, Verdict};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{
+    io::{IsTerminal, Write},
+    time::{Duration, Instant},
 };
-use std::time::Duration;
-use tokio::time::sleep;
 #[allow(unused_imports)] use tracing::{debug, error, info, trace, warn};
 
+pub mod ci;
+pub mod junit;
+
 // ----------------------------------------------------------------------------
-// indicatif tracker loop
+// renderers
+
+/// How tracking progress is rendered
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Render {
+    /// Indicatif progress bar
+    Bar,
+    /// A plain line whenever progress changes
+    Plain,
+}
+
+impl Render {
+    /// Progress bars for terminals, plain lines otherwise
+    pub fn detect() -> Self {
+        if std::io::stdout().is_terminal() {
+            Render::Bar
+        } else {
+            Render::Plain
+        }
+    }
+}
 
 /// Track the rollout of the main workload
 ///
 /// This is currently designed to be called right after a kubectl apply
-/// and may need modifications
-pub async fn workload_rollout(r: &Rollout) -> Result<(bool, State)> {
-    // 1. need to infer properties from the workload first to get information about how to track
-    let params = r.infer_parameters().await?;
-    // 2. use parameters to estimate how long to wait for an upgrade
-    let waittime = estimate::wait_time(&params);
-    // 3. Prepare state, selectors
-    let poll_duration = std::time::Duration::from_millis(1000);
-    let name = r.name.clone();
-    let mut state = State {
-        min_replicas: params.min_replicas, // TODO: maybe update during?
-        hash: None,
-        selector: Selector::default(),
-    };
-    // 4. Use found pod selector on workload to look for child objects
-    let deployment_selector: Selector = params
-        .selector
-        .try_into()
-        .map_err(|e| Error::KubeInvariant(format!("malformed label selector: {e}")))?;
-    state.selector.extend(deployment_selector);
-
-    // 5. Check if we need to actually need to do something first
-    match r.status(&state).await {
-        Ok(rr) => {
-            if rr.ok {
-                return Ok((true, state));
-            } else {
-                debug!("Ignoring rollout failure right after upgrade")
-            }
+/// and may need modifications. Renders progress as picked by `Render::detect`.
+pub async fn workload_rollout(r: &Rollout) -> Result<(Verdict, State)> {
+    Progress::new(Render::detect()).track(r).await
+}
+
+/// Renders the progress of one or more concurrently tracked rollouts
+///
+/// Cheap to clone; all clones draw to the same terminal.
+#[derive(Clone)]
+pub struct Progress {
+    render: Render,
+    periodic: bool,
+    bars: MultiProgress,
+}
+
+impl Progress {
+    pub fn new(render: Render) -> Self {
+        Progress {
+            render,
+            periodic: false,
+            bars: MultiProgress::new(),
         }
-        Err(e) => warn!("Ignoring rollout failure right after upgrade: {}", e),
-    };
-    // Wait for the api server to accept the yaml
-    sleep(poll_duration).await;
-
-    // 6. Determine child objects for the rollout we are following
-    // This is not always sound (multiple upgrades may clash with each other)
-    // A smarter algorithm might change replicasets mid tracking to account for this.
-    info!("Waiting {waittime}s for {name} to rollout (not ready yet)",);
-    // TODO: handle unscheduleble?
-    match r.workload {
-        Kind::Deployment => {
-            // Attempt to find an owning RS hash to track
-            if let Some(rs) = r.get_highest_version_replicaset(&state.selector).await? {
-                if let Some(h) = rs.labels().get("pod-template-hash") {
-                    debug!("Tracking replicaset {}", h);
-                    let expr = Expression::Equal("pod-template-hash".into(), h.clone());
-                    state.hash = Some(h.clone());
-                    state.selector.extend(expr);
-                }
-            }
+    }
+
+    /// Plain lines for every status poll
+    pub fn periodic() -> Self {
+        Progress {
+            periodic: true,
+            ..Progress::new(Render::Plain)
+        }
+    }
+
+    /// Track the rollout of a workload while rendering its progress
+    pub async fn track(&self, r: &Rollout) -> Result<(Verdict, State)> {
+        match self.render {
+            Render::Bar => r.track(&mut Bar::new(&self.bars)).await,
+            Render::Plain => r.track(&mut Plain::new(self.periodic, std::io::stdout())).await,
         }
-        Kind::StatefulSet => {
-            // Attempt to find an owning revesion hash to track
-            let sts = r.get_statefulset().await?;
-            let summary = StatefulSummary::try_from(sts)?;
-            if let Some(ur) = summary.update_revision {
-                debug!("Tracking statefulset {:?} for {}", ur, r.name);
-                state.hash = Some(ur);
+    }
+
+    /// Print other output without garbling progress bars
+    pub fn suspend<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.bars.suspend(f)
+    }
+}
+
+/// Indicatif progress bar observer
+struct Bar(ProgressBar);
+
+impl Bar {
+    fn new(bars: &MultiProgress) -> Self {
+        let pb = bars.add(ProgressBar::new(0));
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("> {bar:40.green/black} {prefix} {pos}/{len} ({elapsed}) {msg}")
+                .expect("valid template string"),
+        );
+        //pb.set_draw_delta(1); removed
+        Bar(pb)
+    }
+}
+
+impl Observer for Bar {
+    fn tracking(&mut self, r: &Rollout, state: &State) {
+        self.0.set_length(state.min_replicas.into());
+        self.0.set_prefix(prefix(r, state));
+    }
+
+    fn message(&mut self, msg: &str) {
+        self.0.set_message(msg.to_string());
+    }
+
+    fn progress(&mut self, rr: &Outcome) {
+        if let Some(msg) = &rr.message {
+            self.0.set_message(msg.clone());
+        }
+        self.0.set_length(rr.expected.into()); // sometimes a replicaset resizes
+        self.0.set_position(rr.progress.into());
+    }
+
+    fn finished(&mut self, verdict: &Verdict) {
+        match verdict {
+            Verdict::Succeeded => self.0.finish(),
+            Verdict::Unchanged => self.0.finish_with_message(verdict.to_string()),
+            Verdict::Paused | Verdict::Superseded(_) | Verdict::WrongVersion(_) | Verdict::Failed(_) => {
+                self.0.abandon_with_message(verdict.to_string())
             }
+            Verdict::TimedOut => self.0.abandon(),
+        }
+    }
+}
+
+/// Plain line observer
+///
+/// Prints "name hash 3/5 (42s) message" when progress or the message changes,
+/// or on every status poll if `periodic`.
+struct Plain<W> {
+    out: W,
+    label: String,
+    start: Instant,
+    periodic: bool,
+    progress: u32,
+    expected: u32,
+    message: String,
+    printed: Option<(u32, u32, String)>,
+}
+
+impl<W: Write> Plain<W> {
+    fn new(periodic: bool, out: W) -> Self {
+        Plain {
+            out,
+            label: String::new(),
+            start: Instant::now(),
+            periodic,
+            progress: 0,
+            expected: 0,
+            message: String::new(),
+            printed: None,
+        }
+    }
+
+    fn print(&mut self, force: bool) {
+        let current = (self.progress, self.expected, self.message.clone());
+        if !force && self.printed.as_ref() == Some(&current) {
+            return;
+        }
+        let secs = self.start.elapsed().as_secs();
+        let mut line = format!("{} {}/{} ({secs}s)", self.label, self.progress, self.expected);
+        if !self.message.is_empty() {
+            line += &format!(" {}", self.message);
+        }
+        writeln!(self.out, "{line}").expect("write progress");
+        self.printed = Some(current);
+    }
+}
+
+impl<W: Write> Observer for Plain<W> {
+    fn tracking(&mut self, r: &Rollout, state: &State) {
+        let name = match &r.cluster {
+            Some(c) => format!("{c}:{}", r.name),
+            None => r.name.clone(),
+        };
+        self.label = match &state.hash {
+            Some(h) => format!("{name} {h}"),
+            None => name,
+        };
+        self.expected = state.min_replicas;
+    }
+
+    fn message(&mut self, msg: &str) {
+        self.message = msg.to_string();
+        if !msg.is_empty() {
+            self.print(false);
         }
-        Kind::DaemonSet => unimplemented!(),
-    }
-
-    let pb = ProgressBar::new(state.min_replicas as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("> {bar:40.green/black} {prefix} {pos}/{len} ({elapsed}) {msg}")
-            .expect("valid template string"),
-    );
-    //pb.set_draw_delta(1); removed
-    if let Some(h) = state.hash.clone() {
-        match r.workload {
-            Kind::Deployment => pb.set_prefix(format!("{name}-{h}")),
-            Kind::StatefulSet => pb.set_prefix(h), // statefulset hash already prefixes name
-            Kind::DaemonSet => pb.set_prefix(h),   // TODO: test
+    }
+
+    fn progress(&mut self, rr: &Outcome) {
+        if let Some(msg) = &rr.message {
+            self.message = msg.clone();
         }
-    } else {
-        pb.set_prefix(name);
-    }
-
-    for i in 1..20 {
-        trace!("poll iteration {}", i);
-        let mut waited = 0;
-        // sleep until 1/20th of estimated upgrade time and poll for status
-        while waited < waittime / 20 {
-            waited += 1;
-            trace!("sleep 1s (waited {})", waited);
-            sleep(Duration::from_secs(1)).await;
+        self.progress = rr.progress;
+        self.expected = rr.expected;
+        self.print(self.periodic);
+    }
+
+    fn finished(&mut self, verdict: &Verdict) {
+        let secs = self.start.elapsed().as_secs();
+        writeln!(self.out, "{} {verdict} after {secs}s", self.label).expect("write progress");
+    }
+}
+
+/// Prefix identifying the tracked revision
+fn prefix(r: &Rollout, state: &State) -> String {
+    let prefix = match (&state.hash, &r.workload) {
+        (Some(h), Kind::Deployment) => format!("{}-{h}", r.name),
+        (Some(h), Kind::StatefulSet) => h.clone(), // statefulset hash already prefixes name
+        (Some(h), Kind::DaemonSet) => h.clone(),   // TODO: test
+        (Some(_), Kind::Job) | (None, _) => r.name.clone(),
+    };
+    match &r.cluster {
+        Some(c) => format!("{c}:{prefix}"),
+        None => prefix,
+    }
+}
+
+// ----------------------------------------------------------------------------
+// reporting
+
+/// Result of tracking a single workload, for reports across workloads
+#[derive(Debug, Clone)]
+pub struct Tracked {
+    /// Workload as namespace/kind/name
+    pub workload: String,
+    /// Version of the tracked revision
+    pub version: Option<String>,
+    /// Time spent tracking
+    pub duration: Duration,
+    /// How the rollout concluded, or why it could not be tracked
+    pub result: std::result::Result<Verdict, String>,
+    /// Findings if the rollout failed
+    pub diagnosis: Option<Diagnosis>,
+}
+
+impl Tracked {
+    /// Whether the rollout concluded successfully
+    pub fn is_success(&self) -> bool {
+        self.result.as_ref().is_ok_and(Verdict::is_success)
+    }
+
+    /// How the rollout concluded as a short string
+    pub fn outcome(&self) -> String {
+        match &self.result {
+            Ok(verdict) => verdict.to_string(),
+            Err(e) => format!("failed: {e}"),
         }
-        let rr = r.status(&state).await?;
-        debug!("RR: {:?}", rr);
-        if let Some(msg) = rr.message {
-            pb.set_message(msg);
+    }
+}
+
+/// Workloads tracked together, before the next stage of a release starts
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub name: String,
+    /// Workloads in the stage as namespace/kind/name
+    pub workloads: Vec<String>,
+    /// How tracking concluded for each workload (empty if the stage was skipped)
+    pub tracked: Vec<Tracked>,
+}
+
+impl Stage {
+    /// Whether every workload in the stage rolled out successfully
+    pub fn is_success(&self) -> bool {
+        !self.tracked.is_empty() && self.tracked.iter().all(Tracked::is_success)
+    }
+
+    /// How the stage concluded as a short string
+    pub fn outcome(&self) -> &'static str {
+        match (self.tracked.is_empty(), self.is_success()) {
+            (true, _) => "skipped",
+            (false, true) => "succeeded",
+            (false, false) => "failed",
+        }
+    }
+}
+
+/// Table of the stages of a release with the outcome of each workload
+pub fn stages_table(stages: &[Stage]) -> String {
+    let header = ["STAGE", "OUTCOME", "WORKLOAD", "RESULT"];
+    let rows = stages.iter().flat_map(|stage| {
+        stage.workloads.iter().enumerate().map(|(i, wl)| {
+            let first = i == 0;
+            vec![
+                if first { stage.name.clone() } else { String::new() },
+                if first {
+                    stage.outcome().to_string()
+                } else {
+                    String::new()
+                },
+                wl.clone(),
+                or_dash(stage.tracked.get(i).map(Tracked::outcome)),
+            ]
+        })
+    });
+    table(&header, rows)
+}
+
+/// Table of workload snapshots with aligned columns
+pub fn status_table(snapshots: &[Snapshot]) -> String {
+    let header = [
+        "WORKLOAD",
+        "CURRENT",
+        "UPDATE",
+        "VERSION",
+        "READY",
+        "UP-TO-DATE",
+        "AVAILABLE",
+        "AGE",
+        "ROLLING",
+    ];
+    let rows = snapshots.iter().map(|s| {
+        vec![
+            s.workload.to_string(),
+            or_dash(s.current_revision.clone()),
+            or_dash(s.update_revision.clone()),
+            or_dash(s.version.clone()),
+            format!("{}/{}", s.ready, s.desired),
+            s.updated.to_string(),
+            s.available.to_string(),
+            s.age(),
+            if s.rolling { "yes" } else { "no" }.to_string(),
+        ]
+    });
+    table(&header, rows)
+}
+
+/// Table of workload revisions with aligned columns
+pub fn history_table(revisions: &[Revision]) -> String {
+    let header = [
+        "REVISION",
+        "HASH",
+        "VERSION",
+        "AGE",
+        "REPLICAS",
+        "OUTCOME",
+        "DURATION",
+        "CHANGE-CAUSE",
+    ];
+    let rows = revisions.iter().map(|r| {
+        vec![
+            r.revision.to_string(),
+            r.hash.clone(),
+            or_dash(r.version.clone()),
+            r.age(),
+            r.replicas.to_string(),
+            or_dash(r.outcome.clone()),
+            or_dash(r.duration.map(|d| format!("{d}s"))),
+            or_dash(r.change_cause.clone()),
+        ]
+    });
+    table(&header, rows)
+}
+
+fn or_dash(v: Option<String>) -> String {
+    v.unwrap_or_else(|| "-".into())
+}
+
+fn table(header: &[&str], rows: impl Iterator<Item = Vec<String>>) -> String {
+    let mut rows = rows.collect::<Vec<_>>();
+    rows.insert(0, header.iter().map(|h| h.to_string()).collect());
+    let widths = (0..header.len())
+        .map(|i| rows.iter().map(|r| r[i].len()).max().unwrap_or_default())
+        .collect::<Vec<_>>();
+    let mut table = String::new();
+    for row in rows {
+        let cells = row.iter().zip(&widths).map(|(c, w)| format!("{c:w$}"));
+        table += cells.collect::<Vec<_>>().join("   ").trim_end();
+        table += "\n";
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(progress: u32, message: &str) -> Outcome {
+        Outcome {
+            progress,
+            expected: 3,
+            message: Some(message.into()),
+            ok: false,
+            failed: None,
         }
-        pb.set_length(rr.expected.into()); // sometimes a replicaset resizes
-        pb.set_position(rr.progress.into());
-        if rr.ok {
-            pb.finish();
-            return Ok((true, state));
+    }
+
+    fn lines(periodic: bool, outcomes: &[Outcome]) -> Vec<String> {
+        let mut plain = Plain::new(periodic, vec![]);
+        plain.label = "web 7c9d8f".into();
+        for o in outcomes {
+            plain.progress(o);
         }
+        let out = String::from_utf8(plain.out).unwrap();
+        out.lines().map(|l| l.replace(" (0s)", "")).collect()
+    }
+
+    #[test]
+    fn plain_prints_changes_or_every_poll() {
+        let polls = [
+            outcome(1, "scaling"),
+            outcome(1, "scaling"),
+            outcome(2, "scaling"),
+        ];
+        assert_eq!(lines(false, &polls), [
+            "web 7c9d8f 1/3 scaling",
+            "web 7c9d8f 2/3 scaling"
+        ]);
+        assert_eq!(lines(true, &polls).len(), 3);
     }
-    Ok((false, state)) // timeout
 }
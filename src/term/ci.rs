@@ -0,0 +1,170 @@
+//! native output for GitHub Actions and GitLab CI
+use super::{Progress, Tracked};
+use crate::{Error, Result};
+
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+#[allow(unused_imports)] use tracing::{debug, error, info, trace, warn};
+
+/// A CI system with its own log conventions
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ci {
+    /// GitHub Actions workflow commands and job summaries
+    #[value(name = "github")]
+    GitHub,
+    /// GitLab CI collapsible sections
+    #[value(name = "gitlab")]
+    GitLab,
+}
+
+impl Ci {
+    /// Detect the CI system from its environment variables
+    pub fn detect() -> Option<Ci> {
+        let set = |var: &str| std::env::var(var).is_ok_and(|v| v == "true");
+        if set("GITHUB_ACTIONS") {
+            Some(Ci::GitHub)
+        } else if set("GITLAB_CI") {
+            Some(Ci::GitLab)
+        } else {
+            None
+        }
+    }
+
+    /// Progress renderer printing a plain line per status poll
+    pub fn progress(self) -> Progress {
+        Progress::periodic()
+    }
+
+    /// Start a collapsible section of the log
+    pub fn group_start(self, title: &str) {
+        match self {
+            Ci::GitHub => println!("::group::{title}"),
+            Ci::GitLab => println!(
+                "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{title}",
+                epoch(),
+                section_id(title)
+            ),
+        }
+    }
+
+    /// End the collapsible section started with the same title
+    pub fn group_end(self, title: &str) {
+        match self {
+            Ci::GitHub => println!("::endgroup::"),
+            Ci::GitLab => println!("\x1b[0Ksection_end:{}:{}\r\x1b[0K", epoch(), section_id(title)),
+        }
+    }
+
+    /// Flag an error in the job output
+    pub fn error(self, title: &str, msg: &str) {
+        match self {
+            Ci::GitHub => println!("{}", github_error(title, msg)),
+            Ci::GitLab => println!("\x1b[31;1mERROR: {title}: {msg}\x1b[0m"),
+        }
+    }
+
+    /// Write a summary of all tracked workloads for the job
+    ///
+    /// Appends a markdown table to `$GITHUB_STEP_SUMMARY` on GitHub; GitLab has no equivalent.
+    pub fn summary(self, tracked: &[Tracked]) -> Result<()> {
+        let Ci::GitHub = self else { return Ok(()) };
+        let Ok(path) = std::env::var("GITHUB_STEP_SUMMARY") else {
+            return Ok(());
+        };
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(Error::Io)?;
+        f.write_all(summary_table(tracked).as_bytes()).map_err(Error::Io)
+    }
+}
+
+fn epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// GitLab section names only allow alphanumerics, `_`, `.` and `-`
+fn section_id(title: &str) -> String {
+    title
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// A GitHub `::error` workflow command with escaped title and message
+fn github_error(title: &str, msg: &str) -> String {
+    let data = |s: &str| s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+    let property = |s: &str| data(s).replace(':', "%3A").replace(',', "%2C");
+    format!("::error title={}::{}", property(title), data(msg))
+}
+
+/// Markdown table of tracked workloads
+fn summary_table(tracked: &[Tracked]) -> String {
+    let cell = |s: &str| s.replace('|', "\\|").replace('\n', " ");
+    let mut md = String::from("### Rollouts\n\n");
+    md += "| | Workload | Version | Outcome | Duration | Diagnosis |\n";
+    md += "| --- | --- | --- | --- | --- | --- |\n";
+    for t in tracked {
+        let icon = if t.is_success() { "✅" } else { "❌" };
+        md += &format!(
+            "| {icon} | `{}` | {} | {} | {}s | {} |\n",
+            t.workload,
+            cell(t.version.as_deref().unwrap_or_default()),
+            cell(&t.outcome()),
+            t.duration.as_secs(),
+            cell(&t.diagnosis.as_ref().map(|d| d.summary()).unwrap_or_default()),
+        );
+    }
+    md + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Verdict;
+    use std::time::Duration;
+
+    #[test]
+    fn escapes_workflow_commands() {
+        assert_eq!(
+            github_error("shop/Deployment/checkout: timed out", "100% broken\nsee logs"),
+            "::error title=shop/Deployment/checkout%3A timed out::100%25 broken%0Asee logs"
+        );
+        assert_eq!(section_id("shop/Deployment/checkout"), "shop_Deployment_checkout");
+    }
+
+    #[test]
+    fn summarizes_workloads() {
+        let tracked = [
+            Tracked {
+                workload: "shop/Deployment/checkout".into(),
+                version: Some("1.4.2".into()),
+                duration: Duration::from_secs(42),
+                result: Ok(Verdict::Succeeded),
+                diagnosis: None,
+            },
+            Tracked {
+                workload: "shop/StatefulSet/db".into(),
+                version: None,
+                duration: Duration::from_secs(3),
+                result: Err("a | b".into()),
+                diagnosis: None,
+            },
+        ];
+        let md = summary_table(&tracked);
+        assert!(md.contains("| ✅ | `shop/Deployment/checkout` | 1.4.2 | succeeded | 42s |  |\n"));
+        assert!(md.contains("| ❌ | `shop/StatefulSet/db` |  | failed: a \\| b | 3s |  |\n"));
+    }
+}
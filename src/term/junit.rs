@@ -0,0 +1,157 @@
+//! JUnit XML reports of tracked workloads
+use super::Tracked;
+use crate::{Error, Result};
+
+use std::path::Path;
+#[allow(unused_imports)] use tracing::{debug, error, info, trace, warn};
+
+/// Write a JUnit report with one testcase per tracked workload
+pub fn write(path: &Path, tracked: &[Tracked]) -> Result<()> {
+    std::fs::write(path, report(tracked)).map_err(Error::Io)
+}
+
+/// JUnit XML for tracked workloads
+///
+/// Unsuccessful rollouts become failures with the diagnosis summary as message and
+/// pod logs in system-out. Rollouts that could not be tracked become errors.
+pub fn report(tracked: &[Tracked]) -> String {
+    let failures = tracked
+        .iter()
+        .filter(|t| matches!(t.result, Ok(ref v) if !v.is_success()));
+    let errors = tracked.iter().filter(|t| t.result.is_err());
+    let total: f64 = tracked.iter().map(|t| t.duration.as_secs_f64()).sum();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!(
+        "<testsuite name=\"irae\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{total:.3}\">\n",
+        tracked.len(),
+        failures.count(),
+        errors.count(),
+    );
+    for t in tracked {
+        xml += &format!(
+            "  <testcase classname=\"irae\" name=\"{}\" time=\"{:.3}\"",
+            escape(&t.workload),
+            t.duration.as_secs_f64()
+        );
+        if t.is_success() {
+            xml += "/>\n";
+            continue;
+        }
+        xml += ">\n";
+        match &t.result {
+            Ok(verdict) => {
+                let message = t.diagnosis.as_ref().map(|d| d.summary());
+                xml += &format!(
+                    "    <failure type=\"{}\" message=\"{}\"/>\n",
+                    escape(&verdict.to_string()),
+                    escape(&message.unwrap_or(verdict.to_string()))
+                );
+            }
+            Err(e) => xml += &format!("    <error message=\"{}\"/>\n", escape(e)),
+        }
+        let pods = t.diagnosis.iter().flat_map(|d| &d.pods);
+        let logs = pods
+            .flat_map(|p| {
+                p.logs
+                    .iter()
+                    .map(|l| format!("--- {} {l} ---\n{}", p.pod.name, l.logs))
+            })
+            .collect::<Vec<_>>();
+        if !logs.is_empty() {
+            xml += &format!("    <system-out>{}</system-out>\n", escape(&logs.join("\n")));
+        }
+        xml += "  </testcase>\n";
+    }
+    xml + "</testsuite>\n"
+}
+
+fn escape(s: &str) -> String {
+    s.chars()
+        .filter(|c| matches!(c, '\t' | '\n' | '\r') || !c.is_control()) // invalid in xml 1.0
+        .fold(String::with_capacity(s.len()), |mut out, c| {
+            match c {
+                '&' => out += "&amp;",
+                '<' => out += "&lt;",
+                '>' => out += "&gt;",
+                '"' => out += "&quot;",
+                '\'' => out += "&apos;",
+                c => out.push(c),
+            }
+            out
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing, Cause, ContainerLogs, Diagnosis, PodDiagnosis, PodSummary, Verdict};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_each_workload() {
+        let tracked = [
+            Tracked {
+                workload: "shop/Deployment/checkout".into(),
+                version: Some("1.4.2".into()),
+                duration: Duration::from_millis(42_500),
+                result: Ok(Verdict::Succeeded),
+                diagnosis: None,
+            },
+            Tracked {
+                workload: "shop/StatefulSet/db".into(),
+                version: None,
+                duration: Duration::from_secs(600),
+                result: Ok(Verdict::TimedOut),
+                diagnosis: None,
+            },
+            Tracked {
+                workload: "shop/DaemonSet/agent".into(),
+                version: None,
+                duration: Duration::from_secs(1),
+                result: Err("pods \"agent\" is <forbidden>".into()),
+                diagnosis: None,
+            },
+        ];
+        let xml = report(&tracked);
+        assert!(xml.contains("tests=\"3\" failures=\"1\" errors=\"1\" time=\"643.500\""));
+        assert!(
+            xml.contains("<testcase classname=\"irae\" name=\"shop/Deployment/checkout\" time=\"42.500\"/>")
+        );
+        assert!(xml.contains("<failure type=\"timed out\" message=\"timed out\"/>"));
+        assert!(xml.contains("<error message=\"pods &quot;agent&quot; is &lt;forbidden&gt;\"/>"));
+    }
+
+    #[test]
+    fn escapes_pod_logs() {
+        let pod = PodSummary::new(testing::pod(json!({})), None).unwrap();
+        let logs = ContainerLogs {
+            container: "app".into(),
+            init: false,
+            previous: true,
+            logs: "panic: <nil> map & slice\nraw ]]> end\u{1b}[0m".into(),
+        };
+        let diagnosis = Diagnosis {
+            cause: Cause::CrashLoop,
+            replicaset: None,
+            pods: vec![PodDiagnosis {
+                pod,
+                logs: vec![logs],
+                node: None,
+                scheduling: None,
+            }],
+            disruption_budgets: vec![],
+        };
+        let failed = Tracked {
+            workload: "shop/Deployment/web".into(),
+            version: None,
+            duration: Duration::from_secs(30),
+            result: Ok(Verdict::Failed("crash looping".into())),
+            diagnosis: Some(diagnosis),
+        };
+        let xml = report(&[failed]);
+        let out = "<system-out>--- web-1 container app (previous instance) ---\n\
+                   panic: &lt;nil&gt; map &amp; slice\nraw ]]&gt; end[0m</system-out>";
+        assert!(xml.contains(out), "{xml}");
+    }
+}
@@ -0,0 +1,108 @@
+//! builders for the objects unit tests decide on
+//!
+//! Each builder starts from a minimal healthy object in the shop namespace, and merges a json patch
+//! over it (objects merge key by key, anything else is replaced).
+use crate::{Inference, State};
+
+use k8s_openapi::api::{
+    apps::v1::{Deployment, ReplicaSet},
+    batch::v1::Job,
+    core::v1::Pod,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+/// Any object from json
+pub fn object<K: DeserializeOwned>(value: Value) -> K {
+    serde_json::from_value(value).expect("valid object")
+}
+
+fn patched<K: DeserializeOwned>(mut base: Value, patch: Value) -> K {
+    merge(&mut base, patch);
+    object(base)
+}
+
+fn merge(base: &mut Value, patch: Value) {
+    match (base, patch) {
+        (Value::Object(base), Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, patch) => *base = patch,
+    }
+}
+
+/// The web deployment, fully rolled out at generation 1 and revision 1
+pub fn deployment(patch: Value) -> Deployment {
+    let base = json!({
+        "metadata": {
+            "name": "web",
+            "namespace": "shop",
+            "generation": 1,
+            "annotations": { "deployment.kubernetes.io/revision": "1" }
+        },
+        "spec": {
+            "replicas": 2,
+            "selector": { "matchLabels": { "app": "web" } },
+            "template": {
+                "metadata": { "labels": { "app": "web" } },
+                "spec": { "containers": [{ "name": "app", "image": "shop/app:1.4.2" }] }
+            }
+        },
+        "status": {
+            "observedGeneration": 1,
+            "replicas": 2,
+            "updatedReplicas": 2,
+            "availableReplicas": 2,
+            "readyReplicas": 2
+        }
+    });
+    patched(base, patch)
+}
+
+/// A replicaset of the web deployment without replicas
+pub fn replicaset(patch: Value) -> ReplicaSet {
+    let base = json!({
+        "metadata": {
+            "name": "web-7c9d8f",
+            "namespace": "shop",
+            "labels": { "app": "web", "pod-template-hash": "7c9d8f" }
+        },
+        "status": { "replicas": 0 }
+    });
+    patched(base, patch)
+}
+
+/// A pod of the web deployment running its app container
+pub fn pod(patch: Value) -> Pod {
+    let base = json!({
+        "metadata": {
+            "name": "web-1",
+            "namespace": "shop",
+            "labels": { "app": "web" }
+        },
+        "spec": { "containers": [{ "name": "app", "image": "shop/app:1.4.2" }] }
+    });
+    patched(base, patch)
+}
+
+/// The migrate job before it started
+pub fn job(patch: Value) -> Job {
+    let base = json!({
+        "metadata": { "name": "migrate", "namespace": "shop" },
+        "spec": { "template": {} }
+    });
+    patched(base, patch)
+}
+
+/// State for tracking a number of replicas, before pinning a revision
+pub fn state(min_replicas: u32) -> State {
+    State::new(&Inference {
+        strategy: None,
+        selector: object(json!({ "matchLabels": { "app": "web" } })),
+        min_replicas,
+        initial_delay_seconds: None,
+    })
+    .expect("valid selector")
+}
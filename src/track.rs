@@ -0,0 +1,190 @@
+//! tracking loop for a rollout, reporting to an `Observer`
+use crate::{estimate, Outcome, PollStrategy, Preflight, Result, Rollout, State, SupersedePolicy, Verdict};
+use kube::ResourceExt;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+#[allow(unused_imports)] use tracing::{debug, error, info, trace, warn};
+
+/// Receiver of progress updates from `Rollout::track`
+///
+/// All methods default to doing nothing so implementors can pick what they care about.
+pub trait Observer {
+    /// The state being tracked changed (initially, or when a revision was pinned)
+    fn tracking(&mut self, _r: &Rollout, _state: &State) {}
+
+    /// Seconds the rollout is estimated to take
+    fn estimated(&mut self, _secs: u32) {}
+
+    /// Human readable progress outside of status polls (empty to clear)
+    fn message(&mut self, _msg: &str) {}
+
+    /// A status poll completed
+    fn progress(&mut self, _outcome: &Outcome) {}
+
+    /// Tracking concluded with a verdict
+    fn finished(&mut self, _verdict: &Verdict) {}
+}
+
+impl Rollout {
+    /// Track the rollout of the workload until it concludes
+    ///
+    /// This is currently designed to be called right after a kubectl apply
+    /// and may need modifications
+    pub async fn track(&self, obs: &mut impl Observer) -> Result<(Verdict, State)> {
+        // 1. need to infer properties from the workload first to get information about how to track
+        let params = self.infer_parameters().await?;
+        let poll_duration = match self.poll {
+            #[cfg(feature = "replay")]
+            PollStrategy::Immediately(_) => Duration::ZERO,
+            _ => Duration::from_millis(1000),
+        };
+        let name = self.name.clone();
+        // 2. Prepare state, selectors from pod selector on workload
+        let mut state = State::new(&params)?;
+        obs.tracking(self, &state);
+
+        // 3. Check if we need to actually need to do something first
+        let mut preflight = self.preflight().await?;
+        for _ in 0..30 {
+            if !matches!(preflight, Preflight::Unobserved { .. }) {
+                break;
+            }
+            obs.message(&preflight.to_string());
+            sleep(poll_duration).await;
+            preflight = self.preflight().await?;
+        }
+        let early = match preflight {
+            Preflight::Paused => Some(Verdict::Paused),
+            Preflight::Unchanged => Some(Verdict::Unchanged),
+            Preflight::Unobserved { .. } => {
+                warn!("{name}: {preflight}, tracking anyway");
+                None
+            }
+            Preflight::Progressing => None,
+        };
+        if let Some(verdict) = early {
+            let verdict = match verdict {
+                Verdict::Unchanged => self.verified(&state, verdict).await?,
+                _ => verdict,
+            };
+            obs.finished(&verdict);
+            return Ok((verdict, state));
+        }
+        obs.message("");
+
+        // 4. use parameters to estimate how long to wait for an upgrade (unless told)
+        let waittime = match self.timeout {
+            Some(timeout) => timeout.as_secs().try_into().unwrap_or(u32::MAX),
+            None => estimate::wait_time(&params),
+        };
+        obs.estimated(waittime);
+        // An autoscaler may change the replica count during the rollout
+        match self.get_autoscaler().await {
+            Ok(Some(hpa)) => {
+                debug!("Following replicas from autoscaler {}", hpa.name_any());
+                state.autoscaler = Some(hpa.name_any());
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Unable to look for autoscalers: {e}"),
+        }
+
+        // 5. Determine child objects for the rollout we are following
+        info!("Waiting {waittime}s for {name} to rollout (not ready yet)",);
+        // TODO: handle unscheduleble?
+        self.pin(&mut state).await?;
+        obs.tracking(self, &state);
+
+        let (interval, polls) = plan(self.poll, waittime);
+        let deadline = Instant::now() + Duration::from_secs(waittime.into());
+        for i in 1..=polls {
+            trace!("poll iteration {}", i);
+            sleep(interval.min(deadline.saturating_duration_since(Instant::now()))).await;
+            // 6. Someone may have applied again while we were tracking
+            if let Some(newer) = self.superseded(&mut state).await? {
+                match self.on_supersede {
+                    SupersedePolicy::Follow => {
+                        warn!("{name} superseded by {newer}, following");
+                        state.pin_hash(&self.workload, newer);
+                        state.generation = self.pin_generation().await?;
+                        obs.tracking(self, &state);
+                    }
+                    SupersedePolicy::Abort => {
+                        let verdict = Verdict::Superseded(newer);
+                        obs.finished(&verdict);
+                        return Ok((verdict, state));
+                    }
+                }
+            }
+            if let Err(e) = self.follow_autoscaler(&mut state).await {
+                warn!("Unable to follow autoscaler: {e}");
+            }
+            let rr = self.status(&state).await?;
+            debug!("RR: {:?}", rr);
+            obs.progress(&rr);
+            if let Some(reason) = rr.failed {
+                let verdict = Verdict::Failed(reason);
+                obs.finished(&verdict);
+                return Ok((verdict, state));
+            }
+            if rr.ok {
+                let verdict = self.verified(&state, Verdict::Succeeded).await?;
+                obs.finished(&verdict);
+                return Ok((verdict, state));
+            }
+            // slow api calls add up, so polls stop at the deadline
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        obs.finished(&Verdict::TimedOut);
+        Ok((Verdict::TimedOut, state))
+    }
+
+    /// Downgrade a successful verdict if ready pods do not run the expected version
+    async fn verified(&self, state: &State, verdict: Verdict) -> Result<Verdict> {
+        let wrong = self.verify_version(state).await?;
+        if wrong.is_empty() {
+            Ok(verdict)
+        } else {
+            Ok(Verdict::WrongVersion(wrong))
+        }
+    }
+}
+
+/// Shortest interval between polls, to not flood the api server
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval between polls and how many polls fit in the wait time (in seconds)
+fn plan(poll: PollStrategy, waittime: u32) -> (Duration, u64) {
+    match poll {
+        // sleep until 1/20th of estimated upgrade time and poll for status
+        PollStrategy::Spread => (Duration::from_secs(waittime.into()) / 20, 19),
+        PollStrategy::Every(interval) => {
+            let interval = interval.max(MIN_INTERVAL);
+            let polls = Duration::from_secs(waittime.into()).div_duration_f64(interval) as u64;
+            (interval, polls.max(1))
+        }
+        #[cfg(feature = "replay")]
+        PollStrategy::Immediately(polls) => (Duration::ZERO, polls),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_polls_within_the_wait_time() {
+        assert_eq!(plan(PollStrategy::Spread, 200), (Duration::from_secs(10), 19));
+        assert_eq!(plan(PollStrategy::Spread, 10), (Duration::from_millis(500), 19));
+        assert_eq!(plan(PollStrategy::Spread, 1), (Duration::from_millis(50), 19));
+        let every = |ms| plan(PollStrategy::Every(Duration::from_millis(ms)), 60);
+        assert_eq!(every(1500), (Duration::from_millis(1500), 40));
+        assert_eq!(every(2500), (Duration::from_millis(2500), 24));
+        assert_eq!(every(250), (Duration::from_millis(250), 240));
+        assert_eq!(every(10), (Duration::from_millis(100), 600));
+        assert_eq!(every(90_000), (Duration::from_secs(90), 1));
+        #[cfg(feature = "replay")]
+        assert_eq!(plan(PollStrategy::Immediately(7), 60), (Duration::ZERO, 7));
+    }
+}
@@ -0,0 +1,118 @@
+//! verification that ready pods run the version that was rolled out
+use crate::{rollout::main_container, Result, Rollout, State};
+
+use k8s_openapi::api::core::v1::Pod;
+use kube::ResourceExt;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+impl Rollout {
+    /// Find ready pods that do not run the expected version
+    ///
+    /// Pods run the expected version when either the main container's image tag or the version label
+    /// (`app.kubernetes.io/version` by default) matches it, so images can be tagged by git sha.
+    /// Expected `sha256:` digests are checked against the digest the image resolved to.
+    /// Returns what each mismatching pod runs (empty when nothing is expected).
+    pub async fn verify_version(&self, state: &State) -> Result<Vec<String>> {
+        let Some(expected) = &self.expect_version else {
+            return Ok(vec![]);
+        };
+        let pods = self.get_pods(&state.selector).await?;
+        let ready = pods.iter().filter(|p| {
+            let conds = p.status.as_ref().and_then(|s| s.conditions.as_ref());
+            let ready = conds
+                .into_iter()
+                .flatten()
+                .any(|c| c.type_ == "Ready" && c.status == "True");
+            ready && p.metadata.deletion_timestamp.is_none()
+        });
+        let (container, label) = (self.container.as_deref(), self.versioning.label.as_str());
+        Ok(ready
+            .filter_map(|p| mismatch(p, expected, container, label))
+            .collect())
+    }
+}
+
+/// What a pod runs when it is not the expected version or digest
+fn mismatch(pod: &Pod, expected: &str, container: Option<&str>, label: &str) -> Option<String> {
+    let name = pod.name_any();
+    let Some(main) = main_container(pod, container) else {
+        return Some(format!("{name} has no containers"));
+    };
+    let image = main.image.as_deref().unwrap_or_default();
+    let found = if expected.starts_with("sha256:") {
+        let statuses = pod
+            .status
+            .iter()
+            .flat_map(|s| s.container_statuses.iter().flatten());
+        let status = statuses.into_iter().find(|s| s.name == main.name);
+        // the image id holds the resolved digest even when the image is referenced by tag
+        let resolved = status.and_then(|s| image_digest(&s.image_id));
+        vec![resolved.or_else(|| image_digest(image))]
+    } else {
+        vec![image_tag(image), pod.labels().get(label).cloned()]
+    };
+    let found = found.into_iter().flatten().collect::<Vec<_>>();
+    match found.first() {
+        _ if found.iter().any(|v| v == expected) => None,
+        Some(other) => Some(format!("{name} runs {other}")),
+        None => Some(format!("{name} runs an unknown version")),
+    }
+}
+
+/// Tag of an image reference, ignoring registry ports and digests
+pub(crate) fn image_tag(image: &str) -> Option<String> {
+    let image = image.split('@').next()?;
+    let (_, tag) = image.rsplit_once(':')?;
+    (!tag.contains('/')).then(|| tag.to_string())
+}
+
+fn image_digest(image: &str) -> Option<String> {
+    image.rsplit_once('@').map(|(_, digest)| digest.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    const LABEL: &str = "app.kubernetes.io/version";
+
+    #[test]
+    fn finds_mismatching_pods() {
+        let pod = |name: &str, image: &str, label: &str| -> Pod {
+            testing::pod(json!({
+                "metadata": { "name": name, "labels": { LABEL: label } },
+                "spec": { "containers": [{ "name": "app", "image": image }] },
+                "status": { "containerStatuses": [{
+                    "name": "app", "image": image, "imageID": "docker.io/shop/app@sha256:abc",
+                    "ready": true, "restartCount": 0
+                }]}
+            }))
+        };
+        let good = pod("web-1", "registry:5000/shop/app:1.4.2", "1.4.2");
+        assert_eq!(mismatch(&good, "1.4.2", None, LABEL), None);
+        assert_eq!(mismatch(&good, "sha256:abc", None, LABEL), None);
+        assert_eq!(
+            mismatch(&good, "sha256:def", None, LABEL).as_deref(),
+            Some("web-1 runs sha256:abc")
+        );
+        let stale = pod("web-2", "shop/app:1.4.1", "1.4.1");
+        assert_eq!(
+            mismatch(&stale, "1.4.2", None, LABEL).as_deref(),
+            Some("web-2 runs 1.4.1")
+        );
+        let sha_tagged = pod("web-4", "shop/app:3f2a9c1", "1.4.2");
+        assert_eq!(mismatch(&sha_tagged, "1.4.2", None, LABEL), None);
+        assert_eq!(mismatch(&sha_tagged, "3f2a9c1", None, LABEL), None);
+        assert_eq!(
+            mismatch(&sha_tagged, "1.4.3", None, LABEL).as_deref(),
+            Some("web-4 runs 3f2a9c1")
+        );
+        let relabelled = pod("web-3", "shop/app@sha256:abc", "1.4.1");
+        assert_eq!(
+            mismatch(&relabelled, "1.4.2", None, LABEL).as_deref(),
+            Some("web-3 runs 1.4.1")
+        );
+    }
+}
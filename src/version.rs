@@ -0,0 +1,178 @@
+//! ordering revisions by the versions they are labelled with
+use crate::{Error, Result};
+
+use kube::{Resource, ResourceExt};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, str::FromStr};
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Standard label with the version of an application
+pub const VERSION_LABEL: &str = "app.kubernetes.io/version";
+
+/// How versions in a label are ordered
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionScheme {
+    /// Semantic versions like 1.4.2
+    #[default]
+    Semver,
+    /// Calendar versions like 2026.10.16-3, ordered by their numbers in turn
+    Calver,
+    /// Opaque versions like git shas, ordered by when their revision was created
+    #[serde(alias = "git")]
+    Created,
+    /// Versions ordered by a custom comparison
+    #[serde(skip)]
+    Custom(fn(&str, &str) -> Ordering),
+}
+
+/// Parse the named schemes (custom orderings can only be set from code)
+impl FromStr for VersionScheme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_ref() {
+            "semver" => Ok(VersionScheme::Semver),
+            "calver" => Ok(VersionScheme::Calver),
+            "created" | "git" => Ok(VersionScheme::Created),
+            _ => Err(Error::InvalidVersion(format!(
+                "unknown version scheme {s}; expected semver, calver or created"
+            ))),
+        }
+    }
+}
+
+/// Which label holds the version of a revision, and how versions are ordered
+#[derive(Clone, Debug)]
+pub struct Versioning {
+    /// Label key on workloads, replicasets and pods
+    pub label: String,
+    pub scheme: VersionScheme,
+}
+
+impl Default for Versioning {
+    fn default() -> Self {
+        Versioning {
+            label: VERSION_LABEL.to_string(),
+            scheme: VersionScheme::default(),
+        }
+    }
+}
+
+impl Versioning {
+    /// The version an object is labelled with
+    pub fn version<'a, K: Resource>(&self, k: &'a K) -> Option<&'a str> {
+        k.labels().get(&self.label).map(String::as_str)
+    }
+
+    /// The object with the newest version (e.g. the leading replicaset of a deployment)
+    pub fn newest<K: Resource>(&self, objects: impl IntoIterator<Item = K>) -> Result<Option<K>> {
+        let mut newest: Option<K> = None;
+        for obj in objects {
+            let newer = match &newest {
+                Some(best) => self.compare(&obj, best)?.is_gt(),
+                None => true,
+            };
+            if newer {
+                newest = Some(obj);
+            }
+        }
+        Ok(newest)
+    }
+
+    /// Compare the versions of two objects
+    pub fn compare<K: Resource>(&self, a: &K, b: &K) -> Result<Ordering> {
+        let ordering = match self.scheme {
+            VersionScheme::Semver => self.semver(a)?.cmp(&self.semver(b)?),
+            VersionScheme::Calver => calver(self.labelled(a)?)?.cmp(&calver(self.labelled(b)?)?),
+            VersionScheme::Created => a.meta().creation_timestamp.cmp(&b.meta().creation_timestamp),
+            VersionScheme::Custom(cmp) => cmp(self.labelled(a)?, self.labelled(b)?),
+        };
+        Ok(ordering)
+    }
+
+    fn labelled<'a, K: Resource>(&self, k: &'a K) -> Result<&'a str> {
+        self.version(k).ok_or_else(|| {
+            let name = k.meta().name.clone().unwrap_or_default();
+            Error::InvalidVersion(format!("{name} has no {} label", self.label))
+        })
+    }
+
+    fn semver<K: Resource>(&self, k: &K) -> Result<Version> {
+        let v = self.labelled(k)?;
+        Version::parse(v).map_err(|e| Error::NonSemverVersion(format!("{}: {v}: {e}", self.label)))
+    }
+}
+
+/// The numbers in a calendar version (any separators)
+fn calver(v: &str) -> Result<Vec<u64>> {
+    let numbers = v
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse::<u64>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| Error::InvalidVersion(format!("{v}: {e}")))?;
+    if numbers.is_empty() {
+        return Err(Error::InvalidVersion(format!("{v}: not a calendar version")));
+    }
+    Ok(numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use k8s_openapi::api::apps::v1::ReplicaSet;
+
+    fn rs(name: &str, version: &str, created: &str) -> ReplicaSet {
+        testing::replicaset(serde_json::json!({
+            "metadata": {
+                "name": name,
+                "creationTimestamp": created,
+                "labels": { VERSION_LABEL: version, "release": version },
+            }
+        }))
+    }
+
+    fn newest(versioning: &Versioning, sets: Vec<ReplicaSet>) -> String {
+        versioning.newest(sets).unwrap().unwrap().name_any()
+    }
+
+    #[test]
+    fn orders_by_scheme() {
+        let sets = || {
+            vec![
+                rs("a", "2026.10.09-1", "2026-10-09T10:00:00Z"),
+                rs("b", "2026.10.16-3", "2026-10-16T10:00:00Z"),
+                rs("c", "2026.10.16-12", "2026-10-15T10:00:00Z"),
+            ]
+        };
+        let calver = Versioning {
+            scheme: VersionScheme::Calver,
+            ..Default::default()
+        };
+        assert_eq!(newest(&calver, sets()), "c");
+        let created = Versioning {
+            label: "release".into(),
+            scheme: VersionScheme::Created,
+        };
+        assert_eq!(newest(&created, sets()), "b");
+        let custom = Versioning {
+            scheme: VersionScheme::Custom(|a, b| b.cmp(a)), // lowest string wins
+            ..Default::default()
+        };
+        assert_eq!(newest(&custom, sets()), "a");
+        let err = Versioning::default().newest(sets()).unwrap_err().to_string();
+        assert!(
+            err.starts_with("Non-semver app.kubernetes.io/version: 2026.10.09-1"),
+            "{err}"
+        );
+        let release = Versioning {
+            label: "release".into(),
+            ..Default::default()
+        };
+        let err = release.newest(sets()).unwrap_err().to_string();
+        assert!(err.starts_with("Non-semver release: 2026.10.09-1"), "{err}");
+    }
+}
@@ -0,0 +1,386 @@
+//! long running observer of rollouts across a cluster
+use crate::{Diagnosis, Error, Kind, Observer, Outcome, Result, Rollout, State, SupersedePolicy, Verdict};
+
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, StatefulSet};
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Resource, ResourceExt,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+#[allow(unused_imports)] use tracing::{debug, error, info, warn};
+
+/// Identifies a workload in a cluster
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct WorkloadRef {
+    pub namespace: String,
+    pub kind: Kind,
+    pub name: String,
+}
+
+impl std::fmt::Display for WorkloadRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.namespace, self.kind, self.name)
+    }
+}
+
+/// Latest knowledge about the rollout of a workload
+#[derive(Clone, Debug, Serialize)]
+pub struct Record {
+    /// Generation of the workload that started the rollout
+    pub generation: Option<i64>,
+    /// When tracking started
+    #[serde(with = "time::serde::rfc3339")]
+    pub started: OffsetDateTime,
+    /// When tracking concluded
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub finished: Option<OffsetDateTime>,
+    /// Estimated seconds for the rollout to complete
+    pub estimate: Option<u32>,
+    /// The tracked state (revision hash, selectors)
+    pub state: Option<State>,
+    /// Last status poll
+    pub outcome: Option<Outcome>,
+    /// Last progress message outside of polls
+    pub message: Option<String>,
+    /// How tracking concluded (if it has)
+    pub verdict: Option<Verdict>,
+    /// Error that stopped tracking
+    pub error: Option<String>,
+    /// Findings if the rollout failed
+    pub diagnosis: Option<Diagnosis>,
+}
+
+impl Record {
+    fn new(generation: Option<i64>) -> Self {
+        Record {
+            generation,
+            started: OffsetDateTime::now_utc(),
+            finished: None,
+            estimate: None,
+            state: None,
+            outcome: None,
+            message: None,
+            verdict: None,
+            error: None,
+            diagnosis: None,
+        }
+    }
+
+    /// Whether the rollout is still being tracked
+    pub fn is_active(&self) -> bool {
+        self.finished.is_none()
+    }
+}
+
+/// In-memory store of the latest rollout record for each workload
+///
+/// Cheap to clone; all clones share the same records.
+#[derive(Clone)]
+pub struct Store {
+    records: Arc<RwLock<BTreeMap<WorkloadRef, Record>>>,
+    updates: broadcast::Sender<(WorkloadRef, Record)>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store {
+            records: Default::default(),
+            updates: broadcast::channel(64).0,
+        }
+    }
+}
+
+impl Store {
+    /// The latest record for a workload
+    pub fn get(&self, key: &WorkloadRef) -> Option<Record> {
+        self.records.read().expect("store lock").get(key).cloned()
+    }
+
+    /// All records, ordered by workload
+    pub fn list(&self) -> Vec<(WorkloadRef, Record)> {
+        let map = self.records.read().expect("store lock");
+        map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// Receive every record as it is updated
+    pub fn subscribe(&self) -> broadcast::Receiver<(WorkloadRef, Record)> {
+        self.updates.subscribe()
+    }
+
+    /// Start a fresh record unless this generation of the workload has already been tracked
+    ///
+    /// Returns whether a record was started.
+    fn start(&self, key: &WorkloadRef, generation: Option<i64>) -> bool {
+        let mut map = self.records.write().expect("store lock");
+        if let Some(rec) = map.get(key) {
+            if rec.is_active() || rec.generation == generation {
+                return false;
+            }
+        }
+        let rec = Record::new(generation);
+        map.insert(key.clone(), rec.clone());
+        let _ = self.updates.send((key.clone(), rec)); // nobody may be listening
+        true
+    }
+
+    fn update(&self, key: &WorkloadRef, f: impl FnOnce(&mut Record)) {
+        let mut map = self.records.write().expect("store lock");
+        if let Some(record) = map.get_mut(key) {
+            f(record);
+            let _ = self.updates.send((key.clone(), record.clone())); // nobody may be listening
+        }
+    }
+}
+
+/// Observer that writes progress into a `Store`
+struct Recorder {
+    store: Store,
+    key: WorkloadRef,
+}
+
+impl Observer for Recorder {
+    fn tracking(&mut self, _r: &Rollout, state: &State) {
+        self.store
+            .update(&self.key, |rec| rec.state = Some(state.clone()));
+    }
+
+    fn estimated(&mut self, secs: u32) {
+        self.store.update(&self.key, |rec| rec.estimate = Some(secs));
+    }
+
+    fn message(&mut self, msg: &str) {
+        let msg = (!msg.is_empty()).then(|| msg.to_string());
+        self.store.update(&self.key, |rec| rec.message = msg);
+    }
+
+    fn progress(&mut self, outcome: &Outcome) {
+        self.store
+            .update(&self.key, |rec| rec.outcome = Some(outcome.clone()));
+    }
+
+    fn finished(&mut self, verdict: &Verdict) {
+        info!("{}: {verdict}", self.key);
+        self.store
+            .update(&self.key, |rec| rec.verdict = Some(verdict.clone()));
+    }
+}
+
+/// Watches workloads and tracks every rollout that starts
+pub struct Watcher {
+    client: kube::Client,
+    namespaces: Vec<String>,
+    store: Store,
+}
+
+impl Watcher {
+    /// Watch the given namespaces (or the whole cluster if empty)
+    pub fn new(client: kube::Client, namespaces: Vec<String>) -> Self {
+        Watcher {
+            client,
+            namespaces,
+            store: Store::default(),
+        }
+    }
+
+    /// Handle to the records of tracked rollouts
+    pub fn store(&self) -> Store {
+        self.store.clone()
+    }
+
+    /// Watch workloads until the process exits
+    pub async fn run(self) -> Result<()> {
+        let mut streams = vec![];
+        if self.namespaces.is_empty() {
+            info!("Watching rollouts in all namespaces");
+            streams.push(rolling::<Deployment>(
+                Api::all(self.client.clone()),
+                Kind::Deployment,
+            ));
+            streams.push(rolling::<StatefulSet>(
+                Api::all(self.client.clone()),
+                Kind::StatefulSet,
+            ));
+            streams.push(rolling::<DaemonSet>(
+                Api::all(self.client.clone()),
+                Kind::DaemonSet,
+            ));
+        } else {
+            for ns in &self.namespaces {
+                info!("Watching rollouts in {ns}");
+                let (c, ns) = (self.client.clone(), ns.as_str());
+                streams.push(rolling::<Deployment>(
+                    Api::namespaced(c.clone(), ns),
+                    Kind::Deployment,
+                ));
+                streams.push(rolling::<StatefulSet>(
+                    Api::namespaced(c.clone(), ns),
+                    Kind::StatefulSet,
+                ));
+                streams.push(rolling::<DaemonSet>(Api::namespaced(c, ns), Kind::DaemonSet));
+            }
+        }
+        let mut events = futures::stream::select_all(streams);
+        while let Some(event) = events.next().await {
+            let (key, generation) = match event {
+                Ok(Some(rolling)) => rolling,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("watch error: {e}");
+                    continue;
+                }
+            };
+            if !self.store.start(&key, generation) {
+                continue; // already tracked
+            }
+            let r = Rollout {
+                name: key.name.clone(),
+                namespace: Some(key.namespace.clone()),
+                workload: key.kind.clone(),
+                on_supersede: SupersedePolicy::Follow,
+                client: self.client.clone(),
+                timeout: None,
+                container: None,
+                expect_version: None,
+                versioning: Default::default(),
+                poll: Default::default(),
+                cluster: None,
+            };
+            tokio::spawn(track(r, key, self.store.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// Track a rollout into the store, and diagnose it if it fails
+async fn track(r: Rollout, key: WorkloadRef, store: Store) {
+    info!("{key}: rollout started");
+    let mut recorder = Recorder {
+        store: store.clone(),
+        key: key.clone(),
+    };
+    let diagnosis = match r.track(&mut recorder).await {
+        Ok((verdict, state)) if verdict.needs_diagnosis() => {
+            match r.diagnose(&state, crate::debug::DEFAULT_LOG_LINES).await {
+                Ok(diag) => {
+                    warn!("{key}: {}", diag.summary());
+                    Some(diag)
+                }
+                Err(e) => {
+                    warn!("{key}: failed to diagnose: {e}");
+                    None
+                }
+            }
+        }
+        Ok(_) => None,
+        Err(e) => {
+            warn!("{key}: failed to track: {e}");
+            store.update(&key, |rec| rec.error = Some(e.to_string()));
+            None
+        }
+    };
+    store.update(&key, |rec| {
+        rec.diagnosis = diagnosis;
+        rec.finished = Some(OffsetDateTime::now_utc());
+    });
+}
+
+/// A workload in the middle of a rollout, with its generation
+type Rolling = (WorkloadRef, Option<i64>);
+
+/// Stream of workloads that are in the middle of a rollout
+fn rolling<K>(api: Api<K>, kind: Kind) -> BoxStream<'static, Result<Option<Rolling>>>
+where
+    K: Resource + Clone + serde::de::DeserializeOwned + std::fmt::Debug + Send + Sync + 'static,
+    K: RolloutStatus,
+{
+    watcher(api, watcher::Config::default())
+        .default_backoff()
+        .applied_objects()
+        .map_ok(move |obj| {
+            let key = WorkloadRef {
+                namespace: obj.namespace().unwrap_or_default(),
+                kind: kind.clone(),
+                name: obj.name_any(),
+            };
+            obj.is_rolling().then_some((key, obj.meta().generation))
+        })
+        .map_err(|e| Error::KubeInvariant(format!("watcher: {e}")))
+        .boxed()
+}
+
+/// Whether a workload object shows a rollout in progress
+pub(crate) trait RolloutStatus {
+    fn is_rolling(&self) -> bool;
+}
+
+fn unobserved(generation: Option<i64>, observed: Option<i64>) -> bool {
+    observed.unwrap_or(0) < generation.unwrap_or(0)
+}
+
+impl RolloutStatus for Deployment {
+    fn is_rolling(&self) -> bool {
+        let (Some(spec), Some(status)) = (&self.spec, &self.status) else {
+            return false;
+        };
+        if spec.paused == Some(true) {
+            return false;
+        }
+        let desired = spec.replicas.unwrap_or(1);
+        unobserved(self.metadata.generation, status.observed_generation)
+            || status.updated_replicas.unwrap_or(0) < desired
+            || status.available_replicas.unwrap_or(0) < desired
+            || status.replicas.unwrap_or(0) > desired // old replicas still around
+    }
+}
+
+impl RolloutStatus for StatefulSet {
+    fn is_rolling(&self) -> bool {
+        let (Some(spec), Some(status)) = (&self.spec, &self.status) else {
+            return false;
+        };
+        let desired = spec.replicas.unwrap_or(1);
+        unobserved(self.metadata.generation, status.observed_generation)
+            || status.current_revision != status.update_revision
+            || status.updated_replicas.unwrap_or(0) < desired
+    }
+}
+
+impl RolloutStatus for DaemonSet {
+    fn is_rolling(&self) -> bool {
+        let Some(status) = &self.status else {
+            return false;
+        };
+        let desired = status.desired_number_scheduled;
+        unobserved(self.metadata.generation, status.observed_generation)
+            || status.updated_number_scheduled.unwrap_or(0) < desired
+            || status.number_available.unwrap_or(0) < desired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_tracks_each_generation_once() {
+        let store = Store::default();
+        let key = WorkloadRef {
+            namespace: "default".into(),
+            kind: Kind::Deployment,
+            name: "app".into(),
+        };
+        assert!(store.start(&key, Some(2)));
+        assert!(!store.start(&key, Some(3)), "still active");
+        store.update(&key, |rec| rec.finished = Some(OffsetDateTime::now_utc()));
+        assert!(!store.start(&key, Some(2)), "generation already tracked");
+        assert!(store.start(&key, Some(3)));
+        assert_eq!(store.list().len(), 1);
+    }
+}